@@ -0,0 +1,85 @@
+//! Compares `Parser::parse_file` with parsing through a `BufReader`. This
+//! only measures reading through a memory map: both parses allocate the
+//! same strings and keys.
+//!
+//! Run with `cargo bench --features mmap`. The size of the generated file
+//! in MiB can be set with `YAJLISH_BENCH_MIB`.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+use yajlish::{combinators::from_fn, Parser, Status};
+
+const RUNS: usize = 5;
+
+fn generate(path: &Path, size: usize) {
+    let mut out = BufWriter::new(File::create(path).unwrap());
+    out.write_all(b"[").unwrap();
+    let mut written = 1;
+    let mut i = 0u64;
+    while written < size {
+        let element = format!(
+            "{}{{\"id\":{},\"name\":\"element {}\",\"score\":{}.5,\"tags\":[\"a\",\"b\\n\"],\"ok\":true}}",
+            if i == 0 { "" } else { "," },
+            i,
+            i,
+            i % 100
+        );
+        out.write_all(element.as_bytes()).unwrap();
+        written += element.len();
+        i += 1;
+    }
+    out.write_all(b"]").unwrap();
+    out.flush().unwrap();
+}
+
+/// The fastest of `RUNS` parses, and the number of events.
+fn time(path: &Path, mmap: bool) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut events = 0;
+    for _ in 0..RUNS {
+        events = 0;
+        let mut handler = from_fn(|_ctx, _event| {
+            events += 1;
+            Status::Continue
+        });
+        let start = Instant::now();
+        let mut parser = Parser::new(&mut handler);
+        if mmap {
+            parser.parse_file(path).unwrap();
+        } else {
+            let mut read = BufReader::new(File::open(path).unwrap());
+            parser.parse(&mut read).unwrap();
+        }
+        parser.finish_parse().unwrap();
+        best = best.min(start.elapsed());
+    }
+    (best, events)
+}
+
+fn main() {
+    let mib = std::env::var("YAJLISH_BENCH_MIB")
+        .ok()
+        .and_then(|mib| mib.parse().ok())
+        .unwrap_or(64);
+    let path = std::env::temp_dir().join(format!("yajlish-bench-{}.json", std::process::id()));
+    generate(&path, mib << 20);
+    let size = std::fs::metadata(&path).unwrap().len() as f64 / f64::from(1 << 20);
+
+    let report = |name: &str, (duration, events): (Duration, usize)| {
+        println!(
+            "{:<12} {:>8.1} ms {:>8.1} MiB/s ({} events)",
+            name,
+            duration.as_secs_f64() * 1000.0,
+            size / duration.as_secs_f64(),
+            events
+        );
+    };
+    report("BufReader", time(&path, false));
+    report("parse_file", time(&path, true));
+
+    std::fs::remove_file(&path).unwrap();
+}
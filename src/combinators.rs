@@ -0,0 +1,665 @@
+//! Combine handlers, so that one `Parser` pass can feed many of them.
+//!
+//! ```
+//!     use yajlish::{
+//!         combinators::{from_fn, HandlerExt},
+//!         value::ValueBuilder,
+//!         Event, Parser, Status,
+//!     };
+//!
+//!     let mut keys = 0;
+//!     let counter = from_fn(|_ctx, event| {
+//!         if let Event::Key(_) = event {
+//!             keys += 1;
+//!         }
+//!         Status::Continue
+//!     });
+//!     let mut tee = ValueBuilder::default().tee(counter);
+//!     Parser::new(&mut tee)
+//!         .parse(&mut "{ \"a\": { \"b\": 1 } }".as_bytes())
+//!         .unwrap();
+//!
+//!     let (builder, _) = tee.into_inner();
+//!     assert_eq!(builder.value().map(|v| v.to_string()), Some("{\"a\":{\"b\":1}}".to_owned()));
+//!     assert_eq!(keys, 2);
+//! ```
+
+#[cfg(feature = "ndjson")]
+use crate::ndjson_handler::{Select, Selector};
+use crate::{Context, ElementError, Event, Handler, KeyId, Status};
+
+/// The kind of event, for tracking where a value ends.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Kind {
+    Value,
+    Key,
+    Start,
+    End,
+}
+
+/// The state of one of the handlers of a `Tee`.
+#[derive(Debug)]
+enum Side {
+    Active,
+    /// The handler returned `Status::SkipValue` and the other one didn't, so
+    /// its events are dropped until the value ends. `depth` is the number of
+    /// open objects and arrays in the value. If it was skipped from a start,
+    /// the end is still passed on.
+    Skipping {
+        depth: usize,
+        from_start: bool,
+    },
+    Aborted,
+}
+
+impl Side {
+    fn dispatch<H, F>(&mut self, handler: &mut H, kind: Kind, f: &F) -> Status
+    where
+        H: Handler,
+        F: Fn(&mut dyn Handler) -> Status,
+    {
+        match self {
+            Side::Active => (),
+            Side::Aborted => return Status::Abort,
+            Side::Skipping { depth, from_start } => {
+                match kind {
+                    Kind::Value if *depth == 0 => *self = Side::Active,
+                    Kind::Start => *depth += 1,
+                    Kind::End => {
+                        *depth -= 1;
+                        if *depth == 0 {
+                            let from_start = *from_start;
+                            *self = Side::Active;
+                            if from_start {
+                                return f(handler);
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+                return Status::Continue;
+            }
+        }
+        let status = f(handler);
+        match status {
+            Status::Abort => *self = Side::Aborted,
+            Status::SkipValue if kind == Kind::Key => {
+                *self = Side::Skipping {
+                    depth: 0,
+                    from_start: false,
+                }
+            }
+            Status::SkipValue if kind == Kind::Start => {
+                *self = Side::Skipping {
+                    depth: 1,
+                    from_start: true,
+                }
+            }
+            Status::SkipValue => return Status::Continue,
+            Status::Continue => (),
+        }
+        status
+    }
+}
+
+/// Passes every event to two handlers.
+///
+/// Once one of them returns `Status::Abort` it gets no more events, and
+/// parsing is only aborted when both have. A value is only skipped by the
+/// parser when both return `Status::SkipValue` for it, or one of them has
+/// aborted; otherwise the events are dropped for the one that skipped it.
+#[derive(Debug)]
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+    sides: (Side, Side),
+}
+
+impl<A: Handler, B: Handler> Tee<A, B> {
+    /// Constructor.
+    pub fn new(first: A, second: B) -> Self {
+        Tee {
+            first,
+            second,
+            sides: (Side::Active, Side::Active),
+        }
+    }
+
+    /// The two handlers.
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+
+    fn dispatch<F: Fn(&mut dyn Handler) -> Status>(&mut self, kind: Kind, f: F) -> Status {
+        let first = self.sides.0.dispatch(&mut self.first, kind, &f);
+        let second = self.sides.1.dispatch(&mut self.second, kind, &f);
+        match (first, second) {
+            (Status::Abort, Status::Abort) => Status::Abort,
+            (Status::Continue, _) | (_, Status::Continue) => Status::Continue,
+            _ => {
+                // The parser skips the value, so neither has to.
+                for side in [&mut self.sides.0, &mut self.sides.1] {
+                    if let Side::Skipping { .. } = side {
+                        *side = Side::Active;
+                    }
+                }
+                Status::SkipValue
+            }
+        }
+    }
+}
+
+impl<A: Handler, B: Handler> Handler for Tee<A, B> {
+    fn handle_null(&mut self, ctx: &Context) -> Status {
+        self.dispatch(Kind::Value, |h| h.handle_null(ctx))
+    }
+
+    fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+        self.dispatch(Kind::Value, |h| h.handle_double(ctx, val))
+    }
+
+    fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+        self.dispatch(Kind::Value, |h| h.handle_int(ctx, val))
+    }
+
+    fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+        self.dispatch(Kind::Value, |h| h.handle_bool(ctx, val))
+    }
+
+    fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+        self.dispatch(Kind::Value, |h| h.handle_string(ctx, val))
+    }
+
+    fn handle_start_map(&mut self, ctx: &Context) -> Status {
+        self.dispatch(Kind::Start, |h| h.handle_start_map(ctx))
+    }
+
+    fn handle_end_map(&mut self, ctx: &Context) -> Status {
+        self.dispatch(Kind::End, |h| h.handle_end_map(ctx))
+    }
+
+    fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+        self.dispatch(Kind::Key, |h| h.handle_map_key(ctx, key))
+    }
+
+    fn handle_interned_key(&mut self, ctx: &Context, id: KeyId, key: &str) -> Status {
+        self.dispatch(Kind::Key, |h| h.handle_interned_key(ctx, id, key))
+    }
+
+    fn handle_start_array(&mut self, ctx: &Context) -> Status {
+        self.dispatch(Kind::Start, |h| h.handle_start_array(ctx))
+    }
+
+    fn handle_end_array(&mut self, ctx: &Context) -> Status {
+        self.dispatch(Kind::End, |h| h.handle_end_array(ctx))
+    }
+
+    fn handle_element_error(&mut self, ctx: &Context, error: &ElementError) -> Status {
+        let handlers: [(&mut Side, &mut dyn Handler); 2] = [
+            (&mut self.sides.0, &mut self.first),
+            (&mut self.sides.1, &mut self.second),
+        ];
+        let mut aborted = true;
+        for (side, handler) in handlers {
+            if let Side::Aborted = side {
+                continue;
+            }
+            // A value being skipped was in the element.
+            *side = match handler.handle_element_error(ctx, error) {
+                Status::Abort => Side::Aborted,
+                _ => {
+                    aborted = false;
+                    Side::Active
+                }
+            };
+        }
+        if aborted {
+            Status::Abort
+        } else {
+            Status::Continue
+        }
+    }
+}
+
+/// Passes only the events of the value at a path of selectors to a handler,
+/// with the `Context` of the whole document. Returns `Status::Abort` once the
+/// value has ended. The values of other keys on the path are skipped.
+///
+/// Requires feature `ndjson`.
+#[cfg(feature = "ndjson")]
+#[derive(Debug)]
+pub struct Filter<H> {
+    handler: H,
+    state: FilterState,
+    /// There are no selectors, so the whole document is passed on.
+    whole: bool,
+}
+
+#[cfg(feature = "ndjson")]
+#[derive(Debug)]
+enum FilterState {
+    Selecting(Select),
+    /// Passing on events, with the number of open objects and arrays in the
+    /// selected value.
+    Forwarding(usize),
+    Done,
+}
+
+#[cfg(feature = "ndjson")]
+impl<H: Handler> Filter<H> {
+    /// Constructor. With no selectors the whole document is passed on.
+    pub fn new(handler: H, selectors: Vec<Selector>) -> Self {
+        let state = match Select::new(selectors) {
+            Some(select) => FilterState::Selecting(select),
+            None => FilterState::Forwarding(0),
+        };
+        let whole = matches!(state, FilterState::Forwarding(_));
+        Filter {
+            handler,
+            state,
+            whole,
+        }
+    }
+
+    /// The handler.
+    pub fn into_inner(self) -> H {
+        self.handler
+    }
+
+    fn dispatch<F: FnOnce(&mut H) -> Status>(&mut self, ctx: &Context, kind: Kind, f: F) -> Status {
+        if let (FilterState::Selecting(select), Kind::Value | Kind::Start) = (&mut self.state, kind)
+        {
+            select.new_array_location(ctx);
+            if select.is_array_index_selector_selection(ctx) && select.remove_last() {
+                self.state = FilterState::Forwarding(0);
+            }
+        }
+        let depth = match &mut self.state {
+            FilterState::Forwarding(depth) => depth,
+            FilterState::Selecting(_) => return Status::Continue,
+            FilterState::Done => return Status::Abort,
+        };
+        match kind {
+            Kind::Start => *depth += 1,
+            Kind::End => *depth -= 1,
+            Kind::Value => (),
+            Kind::Key => unreachable!("keys are passed on by `key`"),
+        }
+        let done = *depth == 0;
+        let status = f(&mut self.handler);
+        if done {
+            self.state = FilterState::Done;
+            return Status::Abort;
+        }
+        status
+    }
+
+    fn key<F: FnOnce(&mut H) -> Status>(&mut self, ctx: &Context, key: &str, f: F) -> Status {
+        match &mut self.state {
+            FilterState::Selecting(select) => {
+                if select.is_identifier_selector_selection(ctx, key) {
+                    if select.remove_last() {
+                        self.state = FilterState::Forwarding(0);
+                    }
+                    Status::Continue
+                } else {
+                    Status::SkipValue
+                }
+            }
+            FilterState::Forwarding(_) => f(&mut self.handler),
+            FilterState::Done => Status::Abort,
+        }
+    }
+}
+
+#[cfg(feature = "ndjson")]
+impl<H: Handler> Handler for Filter<H> {
+    fn handle_null(&mut self, ctx: &Context) -> Status {
+        self.dispatch(ctx, Kind::Value, |h| h.handle_null(ctx))
+    }
+
+    fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+        self.dispatch(ctx, Kind::Value, |h| h.handle_double(ctx, val))
+    }
+
+    fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+        self.dispatch(ctx, Kind::Value, |h| h.handle_int(ctx, val))
+    }
+
+    fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+        self.dispatch(ctx, Kind::Value, |h| h.handle_bool(ctx, val))
+    }
+
+    fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+        self.dispatch(ctx, Kind::Value, |h| h.handle_string(ctx, val))
+    }
+
+    fn handle_start_map(&mut self, ctx: &Context) -> Status {
+        self.dispatch(ctx, Kind::Start, |h| h.handle_start_map(ctx))
+    }
+
+    fn handle_end_map(&mut self, ctx: &Context) -> Status {
+        self.dispatch(ctx, Kind::End, |h| h.handle_end_map(ctx))
+    }
+
+    fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+        self.key(ctx, key, |h| h.handle_map_key(ctx, key))
+    }
+
+    fn handle_interned_key(&mut self, ctx: &Context, id: KeyId, key: &str) -> Status {
+        self.key(ctx, key, |h| h.handle_interned_key(ctx, id, key))
+    }
+
+    fn handle_start_array(&mut self, ctx: &Context) -> Status {
+        self.dispatch(ctx, Kind::Start, |h| h.handle_start_array(ctx))
+    }
+
+    fn handle_end_array(&mut self, ctx: &Context) -> Status {
+        self.dispatch(ctx, Kind::End, |h| h.handle_end_array(ctx))
+    }
+
+    fn handle_element_error(&mut self, ctx: &Context, error: &ElementError) -> Status {
+        match &mut self.state {
+            // Back in the top-level array.
+            FilterState::Forwarding(depth) if self.whole => *depth = 1,
+            // The selected value was in the element.
+            FilterState::Forwarding(_) => self.state = FilterState::Done,
+            FilterState::Selecting(select) if select.is_started() => self.state = FilterState::Done,
+            FilterState::Selecting(_) => (),
+            FilterState::Done => return Status::Abort,
+        }
+        self.handler.handle_element_error(ctx, error)
+    }
+}
+
+/// Changes each event with a closure before passing it to a handler. With
+/// `Parser::key_cache`, interned keys are `Event::InternedKey`.
+#[derive(Debug)]
+pub struct Map<H, F> {
+    handler: H,
+    f: F,
+}
+
+impl<H, F> Map<H, F>
+where
+    H: Handler,
+    F: FnMut(&Context, Event) -> Event,
+{
+    /// Constructor.
+    pub fn new(handler: H, f: F) -> Self {
+        Map { handler, f }
+    }
+
+    /// The handler.
+    pub fn into_inner(self) -> H {
+        self.handler
+    }
+
+    fn dispatch(&mut self, ctx: &Context, event: Event) -> Status {
+        (self.f)(ctx, event).handle(&mut self.handler, ctx)
+    }
+}
+
+impl<H, F> Handler for Map<H, F>
+where
+    H: Handler,
+    F: FnMut(&Context, Event) -> Event,
+{
+    fn handle_null(&mut self, ctx: &Context) -> Status {
+        self.dispatch(ctx, Event::Null)
+    }
+
+    fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+        self.dispatch(ctx, Event::Double(val))
+    }
+
+    fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+        self.dispatch(ctx, Event::Int(val))
+    }
+
+    fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+        self.dispatch(ctx, Event::Bool(val))
+    }
+
+    fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+        self.dispatch(ctx, Event::String(val.to_owned()))
+    }
+
+    fn handle_start_map(&mut self, ctx: &Context) -> Status {
+        self.dispatch(ctx, Event::StartMap)
+    }
+
+    fn handle_end_map(&mut self, ctx: &Context) -> Status {
+        self.dispatch(ctx, Event::EndMap)
+    }
+
+    fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+        self.dispatch(ctx, Event::Key(key.to_owned()))
+    }
+
+    fn handle_interned_key(&mut self, ctx: &Context, id: KeyId, key: &str) -> Status {
+        self.dispatch(ctx, Event::InternedKey(id, key.to_owned()))
+    }
+
+    fn handle_start_array(&mut self, ctx: &Context) -> Status {
+        self.dispatch(ctx, Event::StartArray)
+    }
+
+    fn handle_end_array(&mut self, ctx: &Context) -> Status {
+        self.dispatch(ctx, Event::EndArray)
+    }
+
+    fn handle_element_error(&mut self, ctx: &Context, error: &ElementError) -> Status {
+        self.handler.handle_element_error(ctx, error)
+    }
+}
+
+/// A handler that passes every event to a closure. See `from_fn`.
+#[derive(Debug)]
+pub struct FnHandler<F>(F);
+
+/// A handler that passes every event to a closure. With
+/// `Parser::key_cache`, interned keys are `Event::InternedKey`.
+pub fn from_fn<F: FnMut(&Context, Event) -> Status>(f: F) -> FnHandler<F> {
+    FnHandler(f)
+}
+
+impl<F: FnMut(&Context, Event) -> Status> Handler for FnHandler<F> {
+    fn handle_null(&mut self, ctx: &Context) -> Status {
+        (self.0)(ctx, Event::Null)
+    }
+
+    fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+        (self.0)(ctx, Event::Double(val))
+    }
+
+    fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+        (self.0)(ctx, Event::Int(val))
+    }
+
+    fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+        (self.0)(ctx, Event::Bool(val))
+    }
+
+    fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+        (self.0)(ctx, Event::String(val.to_owned()))
+    }
+
+    fn handle_start_map(&mut self, ctx: &Context) -> Status {
+        (self.0)(ctx, Event::StartMap)
+    }
+
+    fn handle_end_map(&mut self, ctx: &Context) -> Status {
+        (self.0)(ctx, Event::EndMap)
+    }
+
+    fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+        (self.0)(ctx, Event::Key(key.to_owned()))
+    }
+
+    fn handle_interned_key(&mut self, ctx: &Context, id: KeyId, key: &str) -> Status {
+        (self.0)(ctx, Event::InternedKey(id, key.to_owned()))
+    }
+
+    fn handle_start_array(&mut self, ctx: &Context) -> Status {
+        (self.0)(ctx, Event::StartArray)
+    }
+
+    fn handle_end_array(&mut self, ctx: &Context) -> Status {
+        (self.0)(ctx, Event::EndArray)
+    }
+}
+
+/// Methods to chain combinators onto any handler.
+pub trait HandlerExt: Handler + Sized {
+    /// Also pass every event to `other`. See `Tee`.
+    fn tee<B: Handler>(self, other: B) -> Tee<Self, B> {
+        Tee::new(self, other)
+    }
+
+    /// Only pass on the events of the value at the selectors. See `Filter`.
+    ///
+    /// Requires feature `ndjson`.
+    #[cfg(feature = "ndjson")]
+    fn filter(self, selectors: Vec<Selector>) -> Filter<Self> {
+        Filter::new(self, selectors)
+    }
+
+    /// Change each event with a closure before passing it on. See `Map`.
+    fn map<F: FnMut(&Context, Event) -> Event>(self, f: F) -> Map<Self, F> {
+        Map::new(self, f)
+    }
+}
+
+impl<H: Handler> HandlerExt for H {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{value::ValueBuilder, Parser};
+    use pretty_assertions::assert_eq;
+
+    fn parse<H: Handler>(handler: &mut H, input: &str) {
+        Parser::new(handler).parse(&mut input.as_bytes()).unwrap();
+    }
+
+    fn to_string(builder: &ValueBuilder) -> Option<String> {
+        builder.value().map(ToString::to_string)
+    }
+
+    #[test]
+    fn test_tee() {
+        let input = r#"{ "a": [1, 2], "b": { "c": null }, "d": "x" }"#;
+
+        // The second handler aborts at the second key, the first one still
+        // gets every event.
+        let mut keys = 0;
+        let counter = from_fn(|_ctx, event| match event {
+            Event::Key(_) => {
+                keys += 1;
+                if keys == 2 {
+                    Status::Abort
+                } else {
+                    Status::Continue
+                }
+            }
+            _ => Status::Continue,
+        });
+        let mut tee = ValueBuilder::default().tee(counter);
+        parse(&mut tee, input);
+        let (builder, _) = tee.into_inner();
+        assert_eq!(
+            to_string(&builder).as_deref(),
+            Some(r#"{"a":[1,2],"b":{"c":null},"d":"x"}"#)
+        );
+        assert_eq!(keys, 2);
+
+        // The second handler skips "b", the first one still gets it.
+        let skipper = from_fn(|_ctx, event| match event {
+            Event::Key(key) if key == "\"b\"" => Status::SkipValue,
+            _ => Status::Continue,
+        });
+        let mut tee = ValueBuilder::default().tee(ValueBuilder::default().tee(skipper));
+        parse(&mut tee, input);
+        let (first, second) = tee.into_inner();
+        assert_eq!(to_string(&first), to_string(&second.into_inner().0));
+    }
+
+    #[test]
+    fn test_map() {
+        let mut map = ValueBuilder::default().map(|_ctx, event| match event {
+            Event::Int(val) => Event::Int(val * 10),
+            event => event,
+        });
+        parse(&mut map, "[1, [2], 3.5]");
+        assert_eq!(
+            to_string(&map.into_inner()).as_deref(),
+            Some("[10,[20],3.5]")
+        );
+    }
+
+    #[test]
+    fn test_interned_keys() {
+        let mut keys = crate::KeyCache::new();
+        let id = keys.intern("\"a\"").unwrap();
+        let mut seen = vec![];
+        let mut handler = from_fn(|_ctx, event| {
+            seen.push(event);
+            Status::Continue
+        })
+        .map(|_ctx, event| event);
+        let mut parser = Parser::new(&mut handler).key_cache(keys);
+        parser.parse(&mut "{\"a\": 1}".as_bytes()).unwrap();
+        drop(parser);
+        assert_eq!(
+            seen,
+            vec![
+                Event::StartMap,
+                Event::InternedKey(id, "\"a\"".to_owned()),
+                Event::Int(1),
+                Event::EndMap,
+            ]
+        );
+    }
+
+    #[cfg(feature = "ndjson")]
+    #[test]
+    fn test_filter() {
+        use crate::ndjson_handler::SelectorParser;
+
+        let selectors = SelectorParser::new().parse("d.features[1]").unwrap();
+        let mut filter = ValueBuilder::default().filter(selectors);
+        parse(
+            &mut filter,
+            r#"{ "x": { "features": [0] }, "features": [{ "a": 1 }, { "b": [2] }, 3] }"#,
+        );
+        assert_eq!(
+            to_string(&filter.into_inner()).as_deref(),
+            Some(r#"{"b":[2]}"#)
+        );
+    }
+
+    #[cfg(feature = "ndjson")]
+    #[test]
+    fn test_filter_element_error() {
+        use crate::{
+            ndjson_handler::{NdJsonHandler, Route},
+            ParserOptions,
+        };
+
+        // The error comes before the selected value is found, so nothing
+        // from the next element is passed on.
+        let mut out = vec![];
+        let mut rejects = vec![];
+        let route = Route::new(&mut out, vec![]).rejects(&mut rejects);
+        let selectors = vec![Selector::Index(1), Selector::Index(1), Selector::Index(0)];
+        let mut filter = NdJsonHandler::with_routes(vec![route]).filter(selectors);
+        Parser::new(&mut filter)
+            .options(ParserOptions::new().recover_elements(true))
+            .parse(&mut "[[0],[[9],[x]],[[4]]]".as_bytes())
+            .unwrap();
+        drop(filter);
+        assert_eq!(out, b"");
+        assert!(!rejects.is_empty());
+    }
+}
@@ -15,6 +15,7 @@
 * ------------------------------------------------------------------------------
 */
 
+use crate::{ElementError, KeyId};
 use std::collections::VecDeque;
 
 /// The Status that each Handler method returns.
@@ -24,6 +25,14 @@ pub enum Status {
     Continue,
     /// Stop calling methods.
     Abort,
+    /// Skip the value without calling methods for it, only tracking strings
+    /// and the depth of objects and arrays.
+    ///
+    /// From `handle_map_key` the member's value is skipped. From
+    /// `handle_start_map` and `handle_start_array` the contents are skipped,
+    /// and `handle_end_map` or `handle_end_array` is still called. From other
+    /// methods it is the same as `Continue`.
+    SkipValue,
 }
 
 /// Brackets and Braces to keep track of.
@@ -126,6 +135,16 @@ impl Context {
         self.stack.back().copied()
     }
 
+    /// The first enclosing that is still open, which is the outermost.
+    pub(crate) fn first_enclosing(&self) -> Option<Enclosing> {
+        self.stack.front().copied()
+    }
+
+    /// The number of objects and arrays that are open.
+    pub(crate) fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
     /// Remove an enclosing bracket, brace from the stack.
     pub(crate) fn remove_last_enclosing(&mut self) -> Option<Enclosing> {
         self.stack.pop_back()
@@ -174,9 +193,201 @@ pub trait Handler {
     /// Latest parsed value was a key to a JSON object.
     fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status;
 
+    /// Latest parsed value was a key to a JSON object, with its ID in the
+    /// `KeyCache` of the `Parser`. Calls `handle_map_key` unless it is
+    /// implemented.
+    fn handle_interned_key(&mut self, ctx: &Context, _id: KeyId, key: &str) -> Status {
+        self.handle_map_key(ctx, key)
+    }
+
     /// Latest parsed value was a left bracket ([).
     fn handle_start_array(&mut self, ctx: &Context) -> Status;
 
     /// Latest parsed value was a right bracket ([).
     fn handle_end_array(&mut self, ctx: &Context) -> Status;
+
+    /// An element of the top-level array was malformed, and was skipped
+    /// because of `ParserOptions::recover_elements`. Some of its events may
+    /// have been passed to the handler already, without its end.
+    ///
+    /// The default returns `Status::Abort`, and then the error is returned
+    /// from the parse. Return `Status::Continue` to continue with the next
+    /// element.
+    fn handle_element_error(&mut self, _ctx: &Context, _error: &ElementError) -> Status {
+        Status::Abort
+    }
+}
+
+/// Forwards to the handler, so a `Handler` can be passed by reference.
+impl<H: Handler + ?Sized> Handler for &mut H {
+    fn handle_null(&mut self, ctx: &Context) -> Status {
+        (**self).handle_null(ctx)
+    }
+
+    fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+        (**self).handle_double(ctx, val)
+    }
+
+    fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+        (**self).handle_int(ctx, val)
+    }
+
+    fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+        (**self).handle_bool(ctx, val)
+    }
+
+    fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+        (**self).handle_string(ctx, val)
+    }
+
+    fn handle_start_map(&mut self, ctx: &Context) -> Status {
+        (**self).handle_start_map(ctx)
+    }
+
+    fn handle_end_map(&mut self, ctx: &Context) -> Status {
+        (**self).handle_end_map(ctx)
+    }
+
+    fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+        (**self).handle_map_key(ctx, key)
+    }
+
+    fn handle_interned_key(&mut self, ctx: &Context, id: KeyId, key: &str) -> Status {
+        (**self).handle_interned_key(ctx, id, key)
+    }
+
+    fn handle_start_array(&mut self, ctx: &Context) -> Status {
+        (**self).handle_start_array(ctx)
+    }
+
+    fn handle_end_array(&mut self, ctx: &Context) -> Status {
+        (**self).handle_end_array(ctx)
+    }
+
+    fn handle_element_error(&mut self, ctx: &Context, error: &ElementError) -> Status {
+        (**self).handle_element_error(ctx, error)
+    }
+}
+
+/// Forwards to the boxed handler, including a `Box<dyn Handler>`.
+impl<H: Handler + ?Sized> Handler for Box<H> {
+    fn handle_null(&mut self, ctx: &Context) -> Status {
+        (**self).handle_null(ctx)
+    }
+
+    fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+        (**self).handle_double(ctx, val)
+    }
+
+    fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+        (**self).handle_int(ctx, val)
+    }
+
+    fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+        (**self).handle_bool(ctx, val)
+    }
+
+    fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+        (**self).handle_string(ctx, val)
+    }
+
+    fn handle_start_map(&mut self, ctx: &Context) -> Status {
+        (**self).handle_start_map(ctx)
+    }
+
+    fn handle_end_map(&mut self, ctx: &Context) -> Status {
+        (**self).handle_end_map(ctx)
+    }
+
+    fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+        (**self).handle_map_key(ctx, key)
+    }
+
+    fn handle_interned_key(&mut self, ctx: &Context, id: KeyId, key: &str) -> Status {
+        (**self).handle_interned_key(ctx, id, key)
+    }
+
+    fn handle_start_array(&mut self, ctx: &Context) -> Status {
+        (**self).handle_start_array(ctx)
+    }
+
+    fn handle_end_array(&mut self, ctx: &Context) -> Status {
+        (**self).handle_end_array(ctx)
+    }
+
+    fn handle_element_error(&mut self, ctx: &Context, error: &ElementError) -> Status {
+        (**self).handle_element_error(ctx, error)
+    }
+}
+
+/// Decode a JSON string, as it is passed to a `Handler` with its quotes
+/// and escapes. Returns None if it is not a JSON string. Unpaired surrogates
+/// are replaced with U+FFFD.
+pub(crate) fn unescape(raw: &str) -> Option<String> {
+    let inner = raw.strip_prefix('"')?.strip_suffix('"')?;
+    if !inner.contains('\\') {
+        return Some(inner.to_owned());
+    }
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '"' => out.push('"'),
+            '\\' => out.push('\\'),
+            '/' => out.push('/'),
+            'b' => out.push('\u{8}'),
+            'f' => out.push('\u{c}'),
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            'u' => {
+                let high = hex4(&mut chars)?;
+                if (0xD800..0xDC00).contains(&high) && chars.peek() == Some(&'\\') {
+                    let mut lookahead = chars.clone();
+                    lookahead.next();
+                    if lookahead.next() == Some('u') {
+                        if let Some(low) =
+                            hex4(&mut lookahead).filter(|l| (0xDC00..0xE000).contains(l))
+                        {
+                            chars = lookahead;
+                            out.extend(char::from_u32(
+                                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00),
+                            ));
+                            continue;
+                        }
+                    }
+                }
+                out.push(char::from_u32(high).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+fn hex4<I: Iterator<Item = char>>(chars: &mut I) -> Option<u32> {
+    (0..4).try_fold(0, |acc, _| Some(acc * 16 + chars.next()?.to_digit(16)?))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_unescape() {
+        use super::unescape;
+
+        assert_eq!(unescape("\"foo\"").as_deref(), Some("foo"));
+        assert_eq!(
+            unescape(r#""a\"b\\c\/\n\t""#).as_deref(),
+            Some("a\"b\\c/\n\t")
+        );
+        assert_eq!(unescape(r#""\u00e9\ud83d\ude00""#).as_deref(), Some("é😀"));
+        assert_eq!(unescape(r#""\ud83dx""#).as_deref(), Some("\u{fffd}x"));
+        assert_eq!(unescape(r#""\q""#), None);
+        assert_eq!(unescape("foo"), None);
+    }
 }
@@ -0,0 +1,193 @@
+//! Reading compressed JSON. `open` and `decompress` detect gzip, zstd and
+//! bzip2 from their magic bytes, and decompress the input as it is read,
+//! with the features "gzip", "zstd" and "bzip2". Other input is read as it
+//! is.
+//!
+//! ```no_run
+//!     use yajlish::{value::ValueBuilder, Parser};
+//!
+//!     let mut read = yajlish::open("data.json.gz").unwrap();
+//!     let mut builder = ValueBuilder::default();
+//!     Parser::new(&mut builder).parse(&mut read).unwrap();
+//! ```
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Read},
+    path::Path,
+};
+
+/// The compression of the input.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Compression {
+    /// Not compressed, or not in a format that is detected.
+    None,
+    /// gzip, with the feature "gzip".
+    Gzip,
+    /// Zstandard, with the feature "zstd".
+    Zstd,
+    /// bzip2, with the feature "bzip2".
+    Bzip2,
+}
+
+impl Compression {
+    /// The compression of input that starts with `head`, from its magic
+    /// bytes.
+    pub fn detect(head: &[u8]) -> Compression {
+        match head {
+            [0x1F, 0x8B, ..] => Compression::Gzip,
+            [0x28, 0xB5, 0x2F, 0xFD, ..] => Compression::Zstd,
+            [b'B', b'Z', b'h', ..] => Compression::Bzip2,
+            _ => Compression::None,
+        }
+    }
+
+    fn feature(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Bzip2 => "bzip2",
+        }
+    }
+
+    /// The feature for the compression is enabled.
+    pub(crate) fn is_enabled(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Gzip => cfg!(feature = "gzip"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+            Compression::Bzip2 => cfg!(feature = "bzip2"),
+        }
+    }
+
+    /// The file extension, with its dot.
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+            Compression::Bzip2 => ".bz2",
+        }
+    }
+
+    /// The error for a compression whose feature isn't enabled.
+    pub(crate) fn unsupported(self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{:?} requires the feature \"{}\"", self, self.feature()),
+        )
+    }
+}
+
+/// Open a file, decompressing it if it is compressed.
+///
+/// # Errors
+///    - Will return an error Result if the file can't be opened, or it is
+///      compressed in a format whose feature isn't enabled.
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn BufRead>> {
+    decompress(BufReader::new(File::open(path)?))
+}
+
+/// Wrap a reader, decompressing it if it is compressed.
+///
+/// # Errors
+///    - Will return an error Result if the reader returns an error, or the
+///      input is compressed in a format whose feature isn't enabled.
+pub fn decompress<'a, R: BufRead + 'a>(mut read: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    // The magic bytes can be split between reads.
+    let mut head = Vec::with_capacity(4);
+    while head.len() < 4 {
+        let buf = read.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let n = buf.len().min(4 - head.len());
+        head.extend_from_slice(&buf[..n]);
+        read.consume(n);
+    }
+    let compression = Compression::detect(&head);
+    let read = Cursor::new(head).chain(read);
+    match compression {
+        Compression::None => Ok(Box::new(read)),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Ok(Box::new(BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(read),
+        ))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(BufReader::new(
+            zstd::stream::read::Decoder::with_buffer(read)?,
+        ))),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Ok(Box::new(BufReader::new(
+            bzip2::bufread::MultiBzDecoder::new(read),
+        ))),
+        #[allow(unreachable_patterns)]
+        compression => Err(compression.unsupported()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{value::ValueBuilder, Parser};
+    use pretty_assertions::assert_eq;
+    #[cfg(any(feature = "gzip", feature = "bzip2"))]
+    use std::io::Write;
+
+    const JSON: &str = "{\"a\":[1,\"b\",null]}";
+
+    fn parse(input: &[u8]) -> io::Result<Option<String>> {
+        // The magic bytes are split between reads.
+        let mut read = decompress(BufReader::with_capacity(1, input))?;
+        let mut builder = ValueBuilder::default();
+        Parser::new(&mut builder).parse(&mut read).unwrap();
+        Ok(builder.value().map(ToString::to_string))
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Compression::detect(b"\x1F\x8B\x08"), Compression::Gzip);
+        assert_eq!(Compression::detect(b"\x28\xB5\x2F\xFD"), Compression::Zstd);
+        assert_eq!(Compression::detect(b"BZh9"), Compression::Bzip2);
+        assert_eq!(Compression::detect(b"BZ"), Compression::None);
+        assert_eq!(Compression::detect(JSON.as_bytes()), Compression::None);
+    }
+
+    #[test]
+    fn test_decompress() {
+        assert_eq!(parse(JSON.as_bytes()).unwrap(), Some(JSON.to_owned()));
+        assert_eq!(parse(b"1").unwrap(), Some("1".to_owned()));
+
+        #[cfg(not(feature = "gzip"))]
+        assert_eq!(
+            parse(b"\x1F\x8B\x08\x00").unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip() {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(JSON.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(parse(&compressed).unwrap(), Some(JSON.to_owned()));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let compressed = zstd::encode_all(JSON.as_bytes(), 0).unwrap();
+        assert_eq!(parse(&compressed).unwrap(), Some(JSON.to_owned()));
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn test_bzip2() {
+        let mut encoder = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+        encoder.write_all(JSON.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(parse(&compressed).unwrap(), Some(JSON.to_owned()));
+    }
+}
@@ -0,0 +1,617 @@
+//! This Handler implementation converts JSON
+//! with an array into CSV, one row per value in the array.
+//!
+//! Requires feature `ndjson`.
+//!
+
+use crate::{
+    common::unescape,
+    ndjson_handler::{Location, Select, Selector},
+    predicate::Scalar,
+    Context, Enclosing, Handler, Status,
+};
+use std::io::Write;
+
+/// Writes the values of the selected array as CSV rows, with one column for
+/// each path, relative to the value.
+pub struct CsvHandler<OUT> {
+    out: OUT,
+    columns: Vec<Vec<Selector>>,
+    delimiter: u8,
+    quoting: Quoting,
+    header: bool,
+    nested: Nested,
+
+    state: CsvState,
+    row: Row,
+    /// The columns that are written, decided after the first row.
+    layout: Option<Vec<(usize, Option<String>)>>,
+    rows: usize,
+    error: Option<CsvError>,
+}
+
+/// When fields are quoted.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Quoting {
+    /// Quote fields that contain the delimiter, a quote or a line break.
+    Necessary,
+    /// Quote every field.
+    Always,
+    /// Never quote fields.
+    Never,
+}
+
+/// What to do when a column's path selects an object or array.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Nested {
+    /// Write the object or array as JSON in the field.
+    Json,
+    /// Replace the column with one column for each value inside of the object
+    /// or array, named with dotted paths. The columns are decided by the
+    /// first row.
+    Flatten,
+    /// Stop with a `CsvError`.
+    Error,
+}
+
+/// Error converting to CSV.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CsvError {
+    /// The column's path selected an object or array with `Nested::Error`.
+    NestedValue {
+        /// The row, starting at 0.
+        row: usize,
+        /// The column name.
+        column: String,
+    },
+}
+
+impl std::error::Error for CsvError {}
+
+impl std::fmt::Display for CsvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsvError::NestedValue { row, column } => write!(
+                f,
+                "Error: column {} of row {} is an object or array",
+                column, row
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum CsvState {
+    Selecting(Select),
+    /// Writing rows, the root is Some(true) if the selected value is an array.
+    Writing(Option<bool>),
+    Done,
+}
+
+#[derive(Debug, Default)]
+struct Row {
+    stack: Vec<Location>,
+    fields: Vec<Option<String>>,
+    flattened: Vec<Vec<(String, String)>>,
+    json: Option<Json>,
+}
+
+/// A field being written as JSON.
+#[derive(Debug)]
+struct Json {
+    column: usize,
+    depth: usize,
+    text: String,
+    has_values: Vec<bool>,
+    after_key: bool,
+}
+
+impl Json {
+    fn separate(&mut self) {
+        if self.after_key {
+            self.after_key = false;
+        } else if let Some(has_values) = self.has_values.last_mut() {
+            if *has_values {
+                self.text.push(',');
+            }
+            *has_values = true;
+        }
+    }
+}
+
+impl Row {
+    fn path_matches(&self, path: &[Selector]) -> bool {
+        self.stack.len() == path.len()
+            && self
+                .stack
+                .iter()
+                .zip(path)
+                .all(|(location, selector)| location.matches(selector))
+    }
+
+    fn is_inside(&self, path: &[Selector]) -> bool {
+        self.stack.len() > path.len()
+            && self
+                .stack
+                .iter()
+                .zip(path)
+                .all(|(location, selector)| location.matches(selector))
+    }
+
+    /// The dotted path from the first `len` locations to the current value.
+    fn suffix(&self, len: usize) -> String {
+        self.stack[len..]
+            .iter()
+            .map(|location| match location.enclosing {
+                Enclosing::LeftBrace => format!(".{}", key_name(&location.key)),
+                Enclosing::LeftBracket => format!("[{}]", location.count - 1),
+            })
+            .collect()
+    }
+}
+
+fn key_name(key: &str) -> &str {
+    key.strip_prefix('"')
+        .and_then(|k| k.strip_suffix('"'))
+        .unwrap_or(key)
+}
+
+/// The column name for a path, e.g. `properties.name` or `coordinates[0]`.
+fn column_name(path: &[Selector]) -> String {
+    if path.is_empty() {
+        return ".".to_owned();
+    }
+    let name: String = path
+        .iter()
+        .map(|selector| match selector {
+            Selector::Identifier(ident) => format!(".{}", key_name(ident)),
+            Selector::Index(index) => format!("[{}]", index),
+        })
+        .collect();
+    name.trim_start_matches('.').to_owned()
+}
+
+fn field(val: Scalar<'_>) -> String {
+    match val {
+        Scalar::Null => String::new(),
+        Scalar::String(s) => unescape(s).unwrap_or_else(|| s.to_owned()),
+        val => val.to_string(),
+    }
+}
+
+impl<OUT> CsvHandler<OUT>
+where
+    OUT: Write,
+{
+    /// Constructor. The columns are paths relative to each value of
+    /// the selected array.
+    pub fn new(out: OUT, selectors: Vec<Selector>, columns: Vec<Vec<Selector>>) -> Self {
+        let state = match Select::new(selectors) {
+            Some(select) => CsvState::Selecting(select),
+            None => CsvState::Writing(None),
+        };
+        CsvHandler {
+            out,
+            row: Row {
+                fields: vec![None; columns.len()],
+                flattened: vec![vec![]; columns.len()],
+                ..Row::default()
+            },
+            columns,
+            delimiter: b',',
+            quoting: Quoting::Necessary,
+            header: true,
+            nested: Nested::Json,
+            state,
+            layout: None,
+            rows: 0,
+            error: None,
+        }
+    }
+
+    /// The field delimiter, `,` by default. Use `\t` for TSV.
+    #[must_use]
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// When fields are quoted, `Quoting::Necessary` by default.
+    #[must_use]
+    pub fn quoting(mut self, quoting: Quoting) -> Self {
+        self.quoting = quoting;
+        self
+    }
+
+    /// Whether a header row with the column names is written, true by default.
+    #[must_use]
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// What to do with objects and arrays, `Nested::Json` by default.
+    #[must_use]
+    pub fn nested(mut self, nested: Nested) -> Self {
+        self.nested = nested;
+        self
+    }
+
+    /// The error that stopped the conversion, if there was one.
+    #[must_use]
+    pub fn error(&self) -> Option<&CsvError> {
+        self.error.as_ref()
+    }
+
+    fn status(&self) -> Status {
+        if matches!(self.state, CsvState::Done) {
+            Status::Abort
+        } else {
+            Status::Continue
+        }
+    }
+
+    fn select_array_value(&mut self, ctx: &Context) {
+        if let CsvState::Selecting(ref mut select) = &mut self.state {
+            select.new_array_location(ctx);
+            if select.is_array_index_selector_selection(ctx) && select.remove_last() {
+                self.state = CsvState::Writing(None);
+            }
+        }
+    }
+
+    fn enter_value(&mut self) {
+        if let Some(location) = self.row.stack.last_mut() {
+            location.enter_value();
+        }
+        if let Some(json) = &mut self.row.json {
+            json.separate();
+        }
+    }
+
+    fn write_field(&mut self, field: &str) {
+        let quote = match self.quoting {
+            Quoting::Always => true,
+            Quoting::Never => false,
+            Quoting::Necessary => field
+                .bytes()
+                .any(|b| b == self.delimiter || b == b'"' || b == b'\n' || b == b'\r'),
+        };
+        if quote {
+            write!(self.out, "\"{}\"", field.replace('"', "\"\"")).expect("Unable to write");
+        } else {
+            self.out
+                .write_all(field.as_bytes())
+                .expect("Unable to write");
+        }
+    }
+
+    fn write_record<'a, I: Iterator<Item = &'a str>>(&mut self, fields: I) {
+        for (i, field) in fields.enumerate() {
+            if i > 0 {
+                self.out
+                    .write_all(&[self.delimiter])
+                    .expect("Unable to write");
+            }
+            self.write_field(field);
+        }
+        self.out.write_all(b"\n").expect("Unable to write");
+    }
+
+    fn end_row(&mut self) {
+        if self.layout.is_none() {
+            let mut layout = vec![];
+            for (i, flattened) in self.row.flattened.iter().enumerate() {
+                if flattened.is_empty() {
+                    layout.push((i, None));
+                } else {
+                    layout.extend(
+                        flattened
+                            .iter()
+                            .map(|(suffix, _)| (i, Some(suffix.clone()))),
+                    );
+                }
+            }
+            if self.header {
+                let names: Vec<String> = layout
+                    .iter()
+                    .map(|(i, suffix)| {
+                        let name = column_name(&self.columns[*i]);
+                        match suffix {
+                            Some(suffix) if name == "." => {
+                                suffix.trim_start_matches('.').to_owned()
+                            }
+                            Some(suffix) => name + suffix,
+                            None => name,
+                        }
+                    })
+                    .collect();
+                self.write_record(names.iter().map(String::as_str));
+            }
+            self.layout = Some(layout);
+        }
+
+        let row = std::mem::take(&mut self.row.fields);
+        let flattened = std::mem::take(&mut self.row.flattened);
+        if let Some(layout) = self.layout.take() {
+            let fields: Vec<&str> = layout
+                .iter()
+                .map(|(i, suffix)| match suffix {
+                    None => row[*i].as_deref().unwrap_or(""),
+                    Some(suffix) => flattened[*i]
+                        .iter()
+                        .find(|(s, _)| s == suffix)
+                        .map_or("", |(_, v)| v.as_str()),
+                })
+                .collect();
+            self.write_record(fields.into_iter());
+            self.layout = Some(layout);
+        }
+
+        self.row.fields = vec![None; self.columns.len()];
+        self.row.flattened = vec![vec![]; self.columns.len()];
+        self.rows += 1;
+        if matches!(self.state, CsvState::Writing(Some(false))) {
+            self.state = CsvState::Done;
+        }
+    }
+
+    fn value(&mut self, ctx: &Context, val: Scalar<'_>) -> Status {
+        self.select_array_value(ctx);
+
+        if let CsvState::Writing(root) = &mut self.state {
+            root.get_or_insert(false);
+            self.enter_value();
+
+            if let Some(json) = &mut self.row.json {
+                json.text.push_str(&val.to_string());
+            } else if let Some(i) = self
+                .columns
+                .iter()
+                .position(|path| self.row.path_matches(path))
+            {
+                self.row.fields[i] = Some(field(val));
+            } else if let Some(i) = self
+                .columns
+                .iter()
+                .position(|path| self.row.is_inside(path))
+            {
+                let suffix = self.row.suffix(self.columns[i].len());
+                self.row.flattened[i].push((suffix, field(val)));
+            }
+
+            if self.row.stack.is_empty() {
+                self.end_row();
+            }
+        }
+        self.status()
+    }
+
+    fn start(&mut self, ctx: &Context, enclosing: Enclosing) -> Status {
+        self.select_array_value(ctx);
+
+        if let CsvState::Writing(root) = &mut self.state {
+            if root.is_none() && enclosing == Enclosing::LeftBracket {
+                *root = Some(true);
+                return Status::Continue;
+            }
+            root.get_or_insert(false);
+            self.enter_value();
+
+            if let Some(json) = &mut self.row.json {
+                json.text.push(if enclosing == Enclosing::LeftBrace {
+                    '{'
+                } else {
+                    '['
+                });
+                json.has_values.push(false);
+            } else if let Some(i) = self
+                .columns
+                .iter()
+                .position(|path| self.row.path_matches(path))
+            {
+                match self.nested {
+                    Nested::Json => {
+                        self.row.json = Some(Json {
+                            column: i,
+                            depth: self.row.stack.len(),
+                            text: if enclosing == Enclosing::LeftBrace {
+                                "{".to_owned()
+                            } else {
+                                "[".to_owned()
+                            },
+                            has_values: vec![false],
+                            after_key: false,
+                        });
+                    }
+                    Nested::Flatten => (),
+                    Nested::Error => {
+                        self.error = Some(CsvError::NestedValue {
+                            row: self.rows,
+                            column: column_name(&self.columns[i]),
+                        });
+                        self.state = CsvState::Done;
+                        return Status::Abort;
+                    }
+                }
+            }
+            self.row.stack.push(Location::new(enclosing));
+        }
+        self.status()
+    }
+
+    fn end(&mut self, enclosing: Enclosing) -> Status {
+        if let CsvState::Writing(_) = self.state {
+            if self.row.stack.pop().is_none() {
+                self.state = CsvState::Done;
+                return Status::Abort;
+            }
+            if let Some(json) = &mut self.row.json {
+                json.text.push(if enclosing == Enclosing::LeftBrace {
+                    '}'
+                } else {
+                    ']'
+                });
+                json.has_values.pop();
+                if json.depth == self.row.stack.len() {
+                    let json = self.row.json.take().expect("json field");
+                    self.row.fields[json.column] = Some(json.text);
+                }
+            }
+            if self.row.stack.is_empty() {
+                self.end_row();
+            }
+        }
+        self.status()
+    }
+
+    fn map_key(&mut self, ctx: &Context, key: &str) -> Status {
+        match &mut self.state {
+            CsvState::Selecting(select) => {
+                if select.is_identifier_selector_selection(ctx, key) && select.remove_last() {
+                    self.state = CsvState::Writing(None);
+                }
+            }
+            CsvState::Writing(_) => {
+                if let Some(location) = self.row.stack.last_mut() {
+                    location.set_key(key);
+                }
+                if let Some(json) = &mut self.row.json {
+                    json.separate();
+                    json.text.push_str(key);
+                    json.text.push(':');
+                    json.after_key = true;
+                }
+            }
+            CsvState::Done => (),
+        }
+        self.status()
+    }
+}
+
+impl<OUT: Write> Handler for CsvHandler<OUT> {
+    fn handle_null(&mut self, ctx: &Context) -> Status {
+        self.value(ctx, Scalar::Null)
+    }
+
+    fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+        self.value(ctx, Scalar::Double(val))
+    }
+
+    fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+        self.value(ctx, Scalar::Int(val))
+    }
+
+    fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+        self.value(ctx, Scalar::Bool(val))
+    }
+
+    fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+        self.value(ctx, Scalar::String(val))
+    }
+
+    fn handle_start_map(&mut self, ctx: &Context) -> Status {
+        self.start(ctx, Enclosing::LeftBrace)
+    }
+
+    fn handle_end_map(&mut self, _ctx: &Context) -> Status {
+        self.end(Enclosing::LeftBrace)
+    }
+
+    fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+        self.map_key(ctx, key)
+    }
+
+    fn handle_start_array(&mut self, ctx: &Context) -> Status {
+        self.start(ctx, Enclosing::LeftBracket)
+    }
+
+    fn handle_end_array(&mut self, _ctx: &Context) -> Status {
+        self.end(Enclosing::LeftBracket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{predicate::PathParser, Parser};
+    use pretty_assertions::assert_eq;
+
+    fn csv(input: &str, handler: fn(&mut Vec<u8>) -> CsvHandler<&mut Vec<u8>>) -> String {
+        let mut out = vec![];
+        let mut handler = handler(&mut out);
+        let mut parser = Parser::new(&mut handler);
+        parser.parse(&mut input.as_bytes()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn columns(paths: &[&str]) -> Vec<Vec<Selector>> {
+        paths
+            .iter()
+            .map(|p| PathParser::new().parse(p).unwrap())
+            .collect()
+    }
+
+    const INPUT: &str = "{ \"rows\": [{ \"id\": 1, \"name\": \"a, \\\"b\\\"\", \"geo\": { \"x\": 1.5, \"y\": [2, 3] } }, { \"name\": \"c\\nd\", \"id\": 2, \"geo\": null }] }";
+
+    #[test]
+    fn test_csv_json_nested() {
+        assert_eq!(
+            csv(INPUT, |out| CsvHandler::new(
+                out,
+                vec![Selector::Identifier("rows".into())],
+                columns(&[".id", ".name", ".geo"])
+            )),
+            "id,name,geo\n1,\"a, \"\"b\"\"\",\"{\"\"x\"\":1.5,\"\"y\"\":[2,3]}\"\n2,\"c\nd\",\n"
+        );
+    }
+
+    #[test]
+    fn test_tsv_flatten() {
+        assert_eq!(
+            csv(INPUT, |out| CsvHandler::new(
+                out,
+                vec![Selector::Identifier("rows".into())],
+                columns(&[".geo", ".id"])
+            )
+            .delimiter(b'\t')
+            .nested(Nested::Flatten)),
+            "geo.x\tgeo.y[0]\tgeo.y[1]\tid\n1.5\t2\t3\t1\n\t\t\t2\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_options() {
+        assert_eq!(
+            csv("[[1, \"x\"], [2, \"y\"]]", |out| CsvHandler::new(
+                out,
+                vec![],
+                columns(&["[1]", "[0]"])
+            )
+            .header(false)
+            .quoting(Quoting::Always)),
+            "\"x\",\"1\"\n\"y\",\"2\"\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_nested_error() {
+        let mut out = vec![];
+        let mut handler = CsvHandler::new(
+            &mut out,
+            vec![Selector::Identifier("rows".into())],
+            columns(&[".id", ".geo"]),
+        )
+        .nested(Nested::Error);
+        let mut parser = Parser::new(&mut handler);
+        parser.parse(&mut INPUT.as_bytes()).unwrap();
+
+        assert_eq!(
+            handler.error(),
+            Some(&CsvError::NestedValue {
+                row: 0,
+                column: "geo".to_owned()
+            })
+        );
+    }
+}
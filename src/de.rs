@@ -0,0 +1,550 @@
+//! Deserialize JSON into Rust types with serde, without loading the whole
+//! document. A `StreamDeserializer` yields each value of the array at a
+//! selector path one at a time.
+//!
+//! ```
+//!     use serde::Deserialize;
+//!     use yajlish::{de::StreamDeserializer, ndjson_handler::SelectorParser};
+//!
+//!     #[derive(Deserialize)]
+//!     struct Feature {
+//!         id: u64,
+//!     }
+//!
+//!     let input = r#"{ "features": [{ "id": 1 }, { "id": 2 }] }"#;
+//!     let selectors = SelectorParser::new().parse("d.features").unwrap();
+//!     let ids: Vec<u64> = StreamDeserializer::<_, Feature>::new(input.as_bytes(), selectors)
+//!         .map(|feature| feature.unwrap().id)
+//!         .collect();
+//!     assert_eq!(ids, vec![1, 2]);
+//! ```
+//!
+//! Requires feature `serde`, which enables `ndjson` for the selectors.
+//!
+
+use crate::{
+    common::unescape,
+    events::{Event, Events},
+    ndjson_handler::Selector,
+    parser::ParseError,
+};
+use serde::de::{
+    self, value::StringDeserializer, DeserializeOwned, DeserializeSeed, EnumAccess,
+    IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+use std::{io::BufRead, marker::PhantomData};
+
+/// Error deserializing.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The JSON is malformed.
+    Parse(ParseError),
+    /// The JSON ended in the middle of a value.
+    Eof,
+    /// The JSON does not match the type.
+    Message(String),
+}
+
+impl std::error::Error for Error {}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::Eof => write!(f, "Error: Unexpected end of JSON"),
+            Error::Message(msg) => write!(f, "Error: {}", msg),
+        }
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(other: ParseError) -> Self {
+        Error::Parse(other)
+    }
+}
+
+fn unexpected(event: &Event) -> Error {
+    Error::Message(format!("Unexpected {:?}", event))
+}
+
+fn unescape_string(raw: &str) -> Result<String, Error> {
+    unescape(raw).ok_or_else(|| Error::Message(format!("Invalid string {}", raw)))
+}
+
+fn key_deserializer(raw: &str) -> Result<StringDeserializer<Error>, Error> {
+    unescape_string(raw).map(IntoDeserializer::into_deserializer)
+}
+
+/// A serde `Deserializer` that reads JSON events from a reader.
+pub struct Deserializer<R: BufRead> {
+    reader: Events<R>,
+    peeked: Option<Event>,
+}
+
+impl<R: BufRead> Deserializer<R> {
+    /// Constructor.
+    pub fn from_reader(read: R) -> Self {
+        Deserializer {
+            reader: Events::new(read),
+            peeked: None,
+        }
+    }
+
+    /// Check that there is nothing after the value.
+    ///
+    /// # Errors
+    ///    - Returns an error if there is more JSON, or it is malformed.
+    pub fn end(&mut self) -> Result<(), Error> {
+        match self.peek()? {
+            None => Ok(()),
+            Some(event) => Err(Error::Message(format!("Trailing {:?}", event))),
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<&Event>, Error> {
+        if self.peeked.is_none() {
+            self.peeked = self.reader.next_event()?;
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    fn next(&mut self) -> Result<Event, Error> {
+        match self.peeked.take() {
+            Some(event) => Ok(event),
+            None => self.reader.next_event()?.ok_or(Error::Eof),
+        }
+    }
+
+    fn expect(&mut self, expected: Event) -> Result<(), Error> {
+        match self.next()? {
+            event if event == expected => Ok(()),
+            event => Err(unexpected(&event)),
+        }
+    }
+
+    /// Read past the next value.
+    fn skip_value(&mut self) -> Result<(), Error> {
+        if self.peeked.is_none() && self.reader.after_key() {
+            return Ok(self.reader.skip_value()?);
+        }
+        let mut depth = 0usize;
+        loop {
+            match self.next()? {
+                Event::StartMap | Event::StartArray => depth += 1,
+                event @ (Event::EndMap | Event::EndArray) => {
+                    depth = depth.checked_sub(1).ok_or_else(|| unexpected(&event))?;
+                }
+                _ => (),
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Deserialize a `T` from JSON bytes.
+///
+/// # Errors
+///    - Returns an error if the JSON is malformed or does not match `T`.
+pub fn from_reader<R: BufRead, T: DeserializeOwned>(read: R) -> Result<T, Error> {
+    let mut deserializer = Deserializer::from_reader(read);
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+/// Deserialize a `T` from a JSON string.
+///
+/// # Errors
+///    - Returns an error if the JSON is malformed or does not match `T`.
+pub fn from_str<T: DeserializeOwned>(s: &str) -> Result<T, Error> {
+    from_reader(s.as_bytes())
+}
+
+impl<'de, R: BufRead> de::Deserializer<'de> for &mut Deserializer<R> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.next()? {
+            Event::Null => visitor.visit_unit(),
+            Event::Bool(val) => visitor.visit_bool(val),
+            Event::Int(val) => visitor.visit_i64(val),
+            Event::UInt(val) => visitor.visit_u64(val),
+            Event::Double(val) => visitor.visit_f64(val),
+            Event::String(val) => visitor.visit_string(unescape_string(&val)?),
+            Event::StartArray => {
+                let value = visitor.visit_seq(Seq(self))?;
+                self.expect(Event::EndArray)?;
+                Ok(value)
+            }
+            Event::StartMap => {
+                let value = visitor.visit_map(Map(self))?;
+                self.expect(Event::EndMap)?;
+                Ok(value)
+            }
+            event => Err(unexpected(&event)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.peek()? == Some(&Event::Null) {
+            self.next()?;
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.next()? {
+            Event::String(val) => visitor.visit_enum(unescape_string(&val)?.into_deserializer()),
+            Event::StartMap => {
+                let value = visitor.visit_enum(Enum(self))?;
+                self.expect(Event::EndMap)?;
+                Ok(value)
+            }
+            event => Err(unexpected(&event)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.skip_value()?;
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+struct Seq<'a, R: BufRead>(&'a mut Deserializer<R>);
+
+impl<'de, 'a, R: BufRead> SeqAccess<'de> for Seq<'a, R> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.0.peek()? {
+            Some(Event::EndArray) => Ok(None),
+            _ => seed.deserialize(&mut *self.0).map(Some),
+        }
+    }
+}
+
+struct Map<'a, R: BufRead>(&'a mut Deserializer<R>);
+
+impl<'de, 'a, R: BufRead> MapAccess<'de> for Map<'a, R> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.0.peek()? {
+            Some(Event::EndMap) => Ok(None),
+            Some(Event::Key(_)) => match self.0.next()? {
+                Event::Key(key) => seed.deserialize(key_deserializer(&key)?).map(Some),
+                event => Err(unexpected(&event)),
+            },
+            Some(event) => Err(unexpected(event)),
+            None => Err(Error::Eof),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        seed.deserialize(&mut *self.0)
+    }
+}
+
+struct Enum<'a, R: BufRead>(&'a mut Deserializer<R>);
+
+impl<'de, 'a, R: BufRead> EnumAccess<'de> for Enum<'a, R> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Error> {
+        match self.0.next()? {
+            Event::Key(key) => {
+                let variant = seed.deserialize(key_deserializer(&key)?)?;
+                Ok((variant, self))
+            }
+            event => Err(unexpected(&event)),
+        }
+    }
+}
+
+impl<'de, 'a, R: BufRead> VariantAccess<'de> for Enum<'a, R> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self.0)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self.0)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self.0, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self.0, visitor)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum StreamState {
+    Seeking,
+    Array,
+    Value,
+    Done,
+}
+
+/// Yields each value of the array at the selectors as a `T`, or the value itself
+/// if it is not an array. The rest of the document is read past without
+/// being deserialized, and reading stops after the array.
+pub struct StreamDeserializer<R: BufRead, T> {
+    de: Deserializer<R>,
+    selectors: Vec<Selector>,
+    state: StreamState,
+    output: PhantomData<T>,
+}
+
+impl<R: BufRead, T: DeserializeOwned> StreamDeserializer<R, T> {
+    /// Constructor. The selectors are parsed with `SelectorParser`, e.g.
+    /// `d.features` for the `features` member of the document, or `d` for
+    /// the whole document.
+    pub fn new(read: R, selectors: Vec<Selector>) -> Self {
+        StreamDeserializer {
+            de: Deserializer::from_reader(read),
+            selectors,
+            state: StreamState::Seeking,
+            output: PhantomData,
+        }
+    }
+
+    /// Read up to the selected value. Returns false if it is not there.
+    fn seek(&mut self) -> Result<bool, Error> {
+        for selector in &self.selectors {
+            match selector {
+                Selector::Identifier(key) => {
+                    if self.de.next()? != Event::StartMap {
+                        return Ok(false);
+                    }
+                    loop {
+                        match self.de.next()? {
+                            Event::Key(k) if unescape_string(&k)? == *key => break,
+                            Event::Key(_) => self.de.skip_value()?,
+                            _ => return Ok(false),
+                        }
+                    }
+                }
+                Selector::Index(index) => {
+                    if self.de.next()? != Event::StartArray {
+                        return Ok(false);
+                    }
+                    for _ in 0..*index {
+                        if self.de.peek()? == Some(&Event::EndArray) {
+                            return Ok(false);
+                        }
+                        self.de.skip_value()?;
+                    }
+                    if self.de.peek()? == Some(&Event::EndArray) {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn next_value(&mut self) -> Result<Option<T>, Error> {
+        loop {
+            match self.state {
+                StreamState::Seeking => {
+                    if !self.seek()? {
+                        self.state = StreamState::Done;
+                    } else if self.de.peek()? == Some(&Event::StartArray) {
+                        self.de.next()?;
+                        self.state = StreamState::Array;
+                    } else {
+                        self.state = StreamState::Value;
+                    }
+                }
+                StreamState::Array => {
+                    if self.de.peek()? == Some(&Event::EndArray) {
+                        self.state = StreamState::Done;
+                    } else {
+                        return T::deserialize(&mut self.de).map(Some);
+                    }
+                }
+                StreamState::Value => {
+                    self.state = StreamState::Done;
+                    return T::deserialize(&mut self.de).map(Some);
+                }
+                StreamState::Done => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<R: BufRead, T: DeserializeOwned> Iterator for StreamDeserializer<R, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_value() {
+            Ok(value) => value.map(Ok),
+            Err(e) => {
+                self.state = StreamState::Done;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ndjson_handler::SelectorParser;
+    use pretty_assertions::assert_eq;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Kind {
+        Point,
+        Line(u32),
+        Polygon { rings: Vec<Vec<f64>> },
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Feature {
+        id: u64,
+        name: Option<String>,
+        kind: Kind,
+        #[serde(default)]
+        tags: BTreeMap<String, bool>,
+    }
+
+    const INPUT: &str = r#"{
+        "type": "FeatureCollection",
+        "meta": { "skipped": [1, { "a": [] }] },
+        "count": 3,
+        "features": [
+            { "id": 1, "name": "a\"b", "kind": "Point", "extra": [null] },
+            { "id": 2, "name": null, "kind": { "Line": 7 }, "tags": { "x": true } },
+            { "kind": { "Polygon": { "rings": [[1, 2.5]] } }, "id": 3 }
+        ]
+    }"#;
+
+    fn stream<T: DeserializeOwned>(
+        input: &'static str,
+        selectors: &str,
+    ) -> StreamDeserializer<&'static [u8], T> {
+        StreamDeserializer::new(
+            input.as_bytes(),
+            SelectorParser::new().parse(selectors).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_stream_deserializer() {
+        let features: Vec<Feature> = stream(INPUT, "d.features")
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            features,
+            vec![
+                Feature {
+                    id: 1,
+                    name: Some("a\"b".to_owned()),
+                    kind: Kind::Point,
+                    tags: BTreeMap::new(),
+                },
+                Feature {
+                    id: 2,
+                    name: None,
+                    kind: Kind::Line(7),
+                    tags: vec![("x".to_owned(), true)].into_iter().collect(),
+                },
+                Feature {
+                    id: 3,
+                    name: None,
+                    kind: Kind::Polygon {
+                        rings: vec![vec![1.0, 2.5]]
+                    },
+                    tags: BTreeMap::new(),
+                },
+            ]
+        );
+
+        let counts: Vec<u32> = stream(INPUT, "d.count").collect::<Result<_, _>>().unwrap();
+        assert_eq!(counts, vec![3]);
+
+        let ids: Vec<u64> = stream(INPUT, "d.features[1].id")
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(ids, vec![2]);
+
+        assert_eq!(stream::<u64>(INPUT, "d.missing").count(), 0);
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let mut ids = stream::<u64>("[1, \"x\", 3]", "d");
+        assert_eq!(ids.next(), Some(Ok(1)));
+        assert!(matches!(ids.next(), Some(Err(Error::Message(_)))));
+        assert_eq!(ids.next(), None);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(
+            from_str::<(i32, Vec<String>)>("[-1, [\"\\u00e9\"]]"),
+            Ok((-1, vec!["é".to_owned()]))
+        );
+        assert_eq!(from_str::<Vec<u8>>("[1, 2"), Err(Error::Eof));
+        assert!(from_str::<u8>("1 2").is_err());
+    }
+
+    #[test]
+    fn test_large_integers() {
+        assert_eq!(
+            from_str::<Vec<u64>>("[9223372036854775808, 18446744073709551615]"),
+            Ok(vec![1 << 63, u64::MAX])
+        );
+        assert_eq!(
+            from_str::<Vec<i64>>("[-9223372036854775808, 9223372036854775807]"),
+            Ok(vec![i64::MIN, i64::MAX])
+        );
+        assert_eq!(from_str::<f64>("18446744073709551616"), Ok(2f64.powi(64)));
+    }
+}
@@ -0,0 +1,469 @@
+//! Detection of the encoding of the input, and transcoding of UTF-16 and
+//! UTF-32 to UTF-8, so that handlers always get `&str`.
+//!
+//! The encoding is detected from a byte order mark, or from the pattern of
+//! zero bytes in the first four bytes (the first two characters of JSON are
+//! ASCII). [RFC 8259 §8.1] requires UTF-8 for JSON exchanged between
+//! systems, and lets parsers ignore a byte order mark; UTF-16 and UTF-32
+//! are accepted for JSON from older or closed systems. A UTF-8 byte order
+//! mark is skipped.
+//!
+//! [RFC 8259 §8.1]: https://www.rfc-editor.org/rfc/rfc8259#section-8.1
+
+use std::io::{self, BufRead, Read};
+
+/// The encoding of the input.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encoding {
+    /// UTF-8, with or without a byte order mark.
+    Utf8,
+    /// UTF-16, little-endian.
+    Utf16Le,
+    /// UTF-16, big-endian.
+    Utf16Be,
+    /// UTF-32, little-endian.
+    Utf32Le,
+    /// UTF-32, big-endian.
+    Utf32Be,
+}
+
+impl Encoding {
+    /// The encoding of the input starting with `head`, and the length of its
+    /// byte order mark.
+    fn detect(head: &[u8]) -> (Encoding, usize) {
+        match head {
+            [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, 3),
+            [0x00, 0x00, 0xFE, 0xFF, ..] => (Encoding::Utf32Be, 4),
+            [0xFF, 0xFE, 0x00, 0x00, ..] => (Encoding::Utf32Le, 4),
+            [0xFE, 0xFF, ..] => (Encoding::Utf16Be, 2),
+            [0xFF, 0xFE, ..] => (Encoding::Utf16Le, 2),
+            [0x00, 0x00, 0x00, _, ..] => (Encoding::Utf32Be, 0),
+            [_, 0x00, 0x00, 0x00, ..] => (Encoding::Utf32Le, 0),
+            [0x00, _, ..] => (Encoding::Utf16Be, 0),
+            [_, 0x00, ..] => (Encoding::Utf16Le, 0),
+            _ => (Encoding::Utf8, 0),
+        }
+    }
+
+    /// Decode the complete characters at the start of `bytes` to UTF-8,
+    /// returning the number of bytes decoded. At EOF every byte has to be
+    /// decoded. An invalid character is replaced with U+FFFD if `replace` is
+    /// true, or else it is an error with the index of the character, and the
+    /// characters before it are decoded.
+    fn decode(
+        self,
+        bytes: &[u8],
+        eof: bool,
+        replace: bool,
+        out: &mut Vec<u8>,
+    ) -> Result<usize, usize> {
+        let unit = |b: &[u8]| match self {
+            Encoding::Utf16Le => u32::from(u16::from_le_bytes([b[0], b[1]])),
+            Encoding::Utf16Be => u32::from(u16::from_be_bytes([b[0], b[1]])),
+            Encoding::Utf32Le => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            _ => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        };
+        let width = match self {
+            Encoding::Utf8 => unreachable!("UTF-8 isn't transcoded"),
+            Encoding::Utf16Le | Encoding::Utf16Be => 2,
+            Encoding::Utf32Le | Encoding::Utf32Be => 4,
+        };
+        let mut i = 0;
+        while i + width <= bytes.len() {
+            let mut c = unit(&bytes[i..]);
+            let mut len = width;
+            if width == 2 && (0xD800..0xDC00).contains(&c) {
+                if i + 4 > bytes.len() && !eof {
+                    break;
+                }
+                let low = bytes.get(i + 2..i + 4).map(unit);
+                if let Some(low @ 0xDC00..=0xDFFF) = low {
+                    c = 0x10000 + ((c - 0xD800) << 10) + (low - 0xDC00);
+                    len = 4;
+                }
+            }
+            let c = match char::from_u32(c) {
+                Some(c) => c,
+                None if replace => char::REPLACEMENT_CHARACTER,
+                None => return Err(i),
+            };
+            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            i += len;
+        }
+        if eof && i < bytes.len() {
+            if !replace {
+                return Err(i);
+            }
+            out.extend_from_slice("\u{FFFD}".as_bytes());
+            i = bytes.len();
+        }
+        Ok(i)
+    }
+}
+
+/// An invalid character in UTF-16 or UTF-32 input, which is returned from
+/// the `Decoder` in an `io::Error`.
+#[derive(Debug)]
+pub(crate) struct InvalidInput {
+    encoding: Encoding,
+    /// The offset of the character in the input, before transcoding.
+    pub(crate) offset: u64,
+}
+
+impl std::fmt::Display for InvalidInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid {:?} input", self.encoding)
+    }
+}
+
+impl std::error::Error for InvalidInput {}
+
+/// The part of a `Decoder` that is kept when parsing continues on the same
+/// input with a new `Decoder`: the encoding and byte order mark, and input
+/// that was read from the reader but not yet returned.
+#[derive(Debug, Default)]
+pub(crate) struct DecoderState {
+    encoding: Option<Encoding>,
+    /// The length of the byte order mark.
+    bom: usize,
+    /// Bytes to return before reading more, from `pos`: the start of UTF-8
+    /// input that was read to detect the encoding, or transcoded input.
+    buf: Vec<u8>,
+    pos: usize,
+    /// Bytes of a character that hasn't been read completely.
+    partial: Vec<u8>,
+    /// The number of bytes read from the reader, if they are transcoded.
+    read: u64,
+}
+
+impl DecoderState {
+    /// The encoding, once it has been detected.
+    pub(crate) fn encoding(&self) -> Option<Encoding> {
+        self.encoding
+    }
+}
+
+/// Reads the input as UTF-8, transcoding it if it is UTF-16 or UTF-32.
+/// UTF-8 is passed through without copying.
+pub(crate) struct Decoder<R> {
+    read: R,
+    state: DecoderState,
+    /// Replace invalid characters with U+FFFD.
+    replace: bool,
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub(crate) fn new(read: R) -> Self {
+        Decoder {
+            read,
+            state: DecoderState::default(),
+            replace: false,
+        }
+    }
+
+    /// Continue reading the input of an earlier `Decoder`.
+    pub(crate) fn set_state(&mut self, state: DecoderState) {
+        self.state = state;
+    }
+
+    /// Take the state, to continue with `set_state`.
+    pub(crate) fn take_state(&mut self) -> DecoderState {
+        std::mem::take(&mut self.state)
+    }
+
+    /// The encoding, once it has been detected.
+    pub(crate) fn encoding(&self) -> Option<Encoding> {
+        self.state.encoding
+    }
+
+    /// The length of the byte order mark that was skipped.
+    pub(crate) fn bom(&self) -> usize {
+        self.state.bom
+    }
+
+    /// Replace invalid characters in UTF-16 and UTF-32 with U+FFFD instead
+    /// of returning an error.
+    pub(crate) fn set_replace(&mut self, replace: bool) {
+        self.replace = replace;
+    }
+
+    fn detect(&mut self) -> io::Result<()> {
+        let mut head = vec![];
+        while head.len() < 4 {
+            let buf = self.read.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let n = buf.len().min(4 - head.len());
+            head.extend_from_slice(&buf[..n]);
+            self.read.consume(n);
+        }
+        self.state.read = head.len() as u64;
+        if head.is_empty() {
+            return Ok(());
+        }
+        let (encoding, bom) = Encoding::detect(&head);
+        head.drain(..bom);
+        self.state.bom = bom;
+        self.state.encoding = Some(encoding);
+        if encoding == Encoding::Utf8 {
+            self.state.buf = head;
+        } else {
+            self.state.partial = head;
+        }
+        Ok(())
+    }
+
+    /// Transcode the next block of input into `buf`.
+    fn transcode(&mut self, encoding: Encoding) -> io::Result<()> {
+        let state = &mut self.state;
+        state.buf.clear();
+        state.pos = 0;
+        while state.buf.is_empty() {
+            let data = self.read.fill_buf()?;
+            let eof = data.is_empty();
+            state.partial.extend_from_slice(data);
+            let len = data.len();
+            self.read.consume(len);
+            state.read += len as u64;
+            match encoding.decode(&state.partial, eof, self.replace, &mut state.buf) {
+                Ok(decoded) => {
+                    state.partial.drain(..decoded);
+                }
+                // Return the characters before it first.
+                Err(i) if i > 0 => {
+                    state.partial.drain(..i);
+                    break;
+                }
+                Err(_) => {
+                    let offset = state.read - state.partial.len() as u64;
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        InvalidInput { encoding, offset },
+                    ));
+                }
+            }
+            if eof {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let buf = self.fill_buf()?;
+        let n = buf.len().min(out.len());
+        out[..n].copy_from_slice(&buf[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Decoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.state.encoding.is_none() {
+            self.detect()?;
+        }
+        if self.state.pos < self.state.buf.len() {
+            return Ok(&self.state.buf[self.state.pos..]);
+        }
+        match self.state.encoding {
+            None | Some(Encoding::Utf8) => self.read.fill_buf(),
+            Some(encoding) => {
+                self.transcode(encoding)?;
+                Ok(&self.state.buf[self.state.pos..])
+            }
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.state.pos < self.state.buf.len() {
+            self.state.pos += amt;
+        } else {
+            self.read.consume(amt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{combinators::from_fn, Event, ParseError, Parser, ParserOptions, Status};
+    use pretty_assertions::assert_eq;
+    use std::io::BufReader;
+
+    fn encode(s: &str, encoding: Encoding, bom: bool) -> Vec<u8> {
+        let s = if bom {
+            format!("\u{FEFF}{}", s)
+        } else {
+            s.to_owned()
+        };
+        match encoding {
+            Encoding::Utf8 => s.into_bytes(),
+            Encoding::Utf16Le => s.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Encoding::Utf16Be => s.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Encoding::Utf32Le => s.chars().flat_map(|c| (c as u32).to_le_bytes()).collect(),
+            Encoding::Utf32Be => s.chars().flat_map(|c| (c as u32).to_be_bytes()).collect(),
+        }
+    }
+
+    fn decode(input: &[u8], capacity: usize) -> (Option<Encoding>, io::Result<String>) {
+        let mut decoder = Decoder::new(BufReader::with_capacity(capacity, input));
+        let mut out = String::new();
+        let result = decoder.read_to_string(&mut out).map(|_| out);
+        (decoder.encoding(), result)
+    }
+
+    #[test]
+    fn test_decode() {
+        let json = "{\"a\": [\"é\", \"😀\"]}";
+        for encoding in [
+            Encoding::Utf8,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+            Encoding::Utf32Le,
+            Encoding::Utf32Be,
+        ] {
+            for bom in [false, true] {
+                let input = encode(json, encoding, bom);
+                // Characters are split between reads.
+                for capacity in [1, 3, 64] {
+                    let (detected, out) = decode(&input, capacity);
+                    assert_eq!(detected, Some(encoding));
+                    assert_eq!(out.unwrap(), json);
+                }
+            }
+        }
+
+        assert_eq!(decode(b"1", 1).1.unwrap(), "1");
+        let (detected, out) = decode(b"", 1);
+        assert_eq!(detected, None);
+        assert_eq!(out.unwrap(), "");
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // A lone surrogate.
+        let (_, out) = decode(&[b'"', 0, 0x00, 0xD8, b'"', 0], 64);
+        assert_eq!(out.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // Ends in a code unit.
+        let (_, out) = decode(&[b'1', 0, b'2'], 64);
+        assert_eq!(out.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut decoder = Decoder::new(&[b'"', 0, 0x00, 0xD8, b'"', 0, b'2'][..]);
+        decoder.set_replace(true);
+        let mut out = String::new();
+        decoder.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "\"\u{FFFD}\"\u{FFFD}");
+    }
+
+    #[test]
+    fn test_parse_utf16_invalid() {
+        // ["a<U+D800>"], with an unpaired surrogate after 10 bytes.
+        let mut input = encode("[\"a", Encoding::Utf16Le, true);
+        input.extend_from_slice(&[0x00, 0xD8]);
+        input.extend(encode("\"]", Encoding::Utf16Le, false));
+        for capacity in [1, 3, 64] {
+            let mut events = vec![];
+            let mut handler = from_fn(|_ctx, event| {
+                events.push(event);
+                Status::Continue
+            });
+            let mut read = BufReader::with_capacity(capacity, input.as_slice());
+            let result = Parser::new(&mut handler).parse(&mut read);
+            assert_eq!(
+                result,
+                Err(ParseError::Utf8Error {
+                    offset: 8,
+                    message: "Invalid Utf16Le input".to_owned(),
+                })
+            );
+            // The events before it are still passed on.
+            assert_eq!(events, vec![Event::StartArray]);
+        }
+
+        let options = ParserOptions::new().replace_invalid_utf8(true);
+        let mut strings = vec![];
+        let mut handler = from_fn(|_ctx, event| {
+            if let Event::String(s) = event {
+                strings.push(s);
+            }
+            Status::Continue
+        });
+        Parser::new(&mut handler)
+            .options(options)
+            .parse(&mut input.as_slice())
+            .unwrap();
+        assert_eq!(strings, vec!["\"a\u{FFFD}\""]);
+    }
+
+    #[test]
+    fn test_parse_utf16() {
+        let input = encode("[\"Ünïcödé\", 1]", Encoding::Utf16Le, true);
+        let mut strings = vec![];
+        let mut handler = from_fn(|_ctx, event| {
+            if let Event::String(s) = event {
+                strings.push(s);
+            }
+            Status::Continue
+        });
+        let mut parser = Parser::new(&mut handler);
+        parser.parse(&mut input.as_slice()).unwrap();
+        parser.finish_parse().unwrap();
+        assert_eq!(parser.encoding(), Some(Encoding::Utf16Le));
+        drop(parser);
+        assert_eq!(strings, vec!["\"Ünïcödé\""]);
+    }
+
+    #[test]
+    fn test_resume_after_abort() {
+        let json = "[\"ä\", {\"b\": [1, 2]}, \"😀\"]";
+        for encoding in [
+            Encoding::Utf8,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+            Encoding::Utf32Le,
+            Encoding::Utf32Be,
+        ] {
+            let input = encode(json, encoding, true);
+            let mut events = vec![];
+            let mut handler = from_fn(|_ctx, event| {
+                events.push(format!("{:?}", event));
+                // Abort after every second event.
+                if events.len() % 2 == 0 {
+                    Status::Abort
+                } else {
+                    Status::Continue
+                }
+            });
+            let mut parser = Parser::new(&mut handler);
+            let mut read = BufReader::with_capacity(7, input.as_slice());
+            for _ in 0..6 {
+                parser.parse(&mut read).unwrap();
+            }
+            parser.finish_parse().unwrap();
+            assert_eq!(parser.encoding(), Some(encoding));
+            drop(parser);
+            assert_eq!(
+                events,
+                vec![
+                    "StartArray",
+                    "String(\"\\\"ä\\\"\")",
+                    "StartMap",
+                    "Key(\"\\\"b\\\"\")",
+                    "StartArray",
+                    "Int(1)",
+                    "Int(2)",
+                    "EndArray",
+                    "EndMap",
+                    "String(\"\\\"😀\\\"\")",
+                    "EndArray",
+                ],
+                "{:?}",
+                encoding
+            );
+        }
+    }
+}
@@ -0,0 +1,983 @@
+//! A pull parser: reads JSON events one at a time. The `Parser` passes each
+//! event to a `Handler`.
+//!
+//! ```
+//!     use yajlish::{Event, Events};
+//!
+//!     let mut events = Events::new("{ \"a\": [1, true] }".as_bytes());
+//!     let mut ints = vec![];
+//!     while let Some(event) = events.next() {
+//!         if let Event::Int(val) = event.unwrap() {
+//!             ints.push((val, events.context().num_open_brackets()));
+//!         }
+//!     }
+//!     assert_eq!(ints, vec![(1, 1)]);
+//! ```
+
+use crate::{
+    common::{Enclosing, ParserStatus},
+    encoding::DecoderState,
+    lexer::{Lexer, Token},
+    parser::{ElementError, ParseError, ParserOptions},
+    Context, Encoding, Handler, KeyCache, KeyId, Status,
+};
+use std::io::BufRead;
+
+/// A JSON event, one for each `Handler` method. Strings and keys are as they
+/// were in the JSON, with their quotes and escapes.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event {
+    /// `null`, see `Handler::handle_null`.
+    Null,
+    /// `true` or `false`, see `Handler::handle_bool`.
+    Bool(bool),
+    /// A number that fits in an i64, see `Handler::handle_int`.
+    Int(i64),
+    /// An integer above `i64::MAX` that fits in a u64. Handlers get it with
+    /// `Handler::handle_double`.
+    UInt(u64),
+    /// Any other number, see `Handler::handle_double`.
+    Double(f64),
+    /// A string value, see `Handler::handle_string`.
+    String(String),
+    /// An object key, see `Handler::handle_map_key`.
+    Key(String),
+    /// An object key with its ID in a `KeyCache`, see
+    /// `Handler::handle_interned_key`. `Events` doesn't intern keys, so this
+    /// is only passed to the closures of `combinators`.
+    InternedKey(KeyId, String),
+    /// `{`, see `Handler::handle_start_map`.
+    StartMap,
+    /// `}`, see `Handler::handle_end_map`.
+    EndMap,
+    /// `[`, see `Handler::handle_start_array`.
+    StartArray,
+    /// `]`, see `Handler::handle_end_array`.
+    EndArray,
+}
+
+impl Event {
+    /// Call the `Handler` method for this event.
+    pub fn handle<H: Handler + ?Sized>(&self, handler: &mut H, ctx: &Context) -> Status {
+        match self {
+            Event::Null => handler.handle_null(ctx),
+            Event::Bool(val) => handler.handle_bool(ctx, *val),
+            Event::Int(val) => handler.handle_int(ctx, *val),
+            Event::UInt(val) => handler.handle_double(ctx, *val as f64),
+            Event::Double(val) => handler.handle_double(ctx, *val),
+            Event::String(val) => handler.handle_string(ctx, val),
+            Event::Key(key) => handler.handle_map_key(ctx, key),
+            Event::InternedKey(id, key) => handler.handle_interned_key(ctx, *id, key),
+            Event::StartMap => handler.handle_start_map(ctx),
+            Event::EndMap => handler.handle_end_map(ctx),
+            Event::StartArray => handler.handle_start_array(ctx),
+            Event::EndArray => handler.handle_end_array(ctx),
+        }
+    }
+}
+
+/// Reads events from JSON bytes, as an `Iterator`.
+///
+/// The `Context` is updated for an event when the next one is read, so after
+/// `next` it is the `Context` a `Handler` gets for that event. The iterator
+/// ends after an error, unless it was recovered from because of
+/// `ParserOptions::recover_elements`.
+pub struct Events<R: BufRead> {
+    lexer: Lexer<R>,
+    context: Context,
+    /// The event whose `Context` update is deferred.
+    pending: Option<Event>,
+    failed: bool,
+    options: ParserOptions,
+    /// The number of values read.
+    elements: u64,
+    truncated: bool,
+    /// The number of elements of the top-level array that were started.
+    array_elements: usize,
+    /// The last error, if it was recovered from.
+    recovered: Option<ElementError>,
+    /// Tokens that were read ahead, last first.
+    lookahead: Vec<Token>,
+    /// The keys to intern, with `Parser::key_cache`.
+    keys: Option<KeyCache>,
+    /// The ID of the last key, if it was interned by the lexer. Its
+    /// `Event::Key` is empty, so that the key isn't allocated.
+    interned: Option<KeyId>,
+}
+
+/// The counts that limits, offsets and element indexes are based on, which
+/// are kept when parsing continues with a new `Events`.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Counts {
+    /// The number of bytes consumed, not including a byte order mark.
+    bytes: u64,
+    elements: u64,
+    array_elements: usize,
+}
+
+impl<R: BufRead> Events<R> {
+    /// Constructor.
+    pub fn new(read: R) -> Self {
+        Self::with_context(read, Context::default())
+    }
+
+    /// Continue parsing with the `Context` of an earlier `Events`.
+    pub(crate) fn with_context(read: R, context: Context) -> Self {
+        Events {
+            lexer: Lexer::new(read),
+            context,
+            pending: None,
+            failed: false,
+            options: ParserOptions::default(),
+            elements: 0,
+            truncated: false,
+            array_elements: 0,
+            recovered: None,
+            lookahead: vec![],
+            keys: None,
+            interned: None,
+        }
+    }
+
+    /// Limit the resources used by the parse, see `ParserOptions`.
+    #[must_use]
+    pub fn options(mut self, options: ParserOptions) -> Self {
+        self.lexer.set_options(options);
+        self.options = options;
+        self
+    }
+
+    /// The encoding of the input, once it has been detected.
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.lexer.encoding()
+    }
+
+    /// Continue parsing the input of an earlier `Events`, in its encoding
+    /// and from the input it had read but not parsed.
+    pub(crate) fn set_decoder_state(&mut self, state: DecoderState) {
+        self.lexer.set_decoder_state(state);
+    }
+
+    /// Take the state of the decoder, to continue with `set_decoder_state`.
+    pub(crate) fn take_decoder_state(&mut self) -> DecoderState {
+        self.lexer.take_decoder_state()
+    }
+
+    /// Intern keys in `keys`, and pass them to `Handler::handle_interned_key`
+    /// in `drive`.
+    pub(crate) fn set_key_cache(&mut self, keys: Option<KeyCache>) {
+        self.keys = keys;
+    }
+
+    /// Take the `KeyCache` back.
+    pub(crate) fn take_key_cache(&mut self) -> Option<KeyCache> {
+        self.keys.take()
+    }
+
+    /// Continue with the tokens an earlier `Events` read ahead.
+    pub(crate) fn set_lookahead(&mut self, lookahead: Vec<Token>) {
+        self.lookahead = lookahead;
+    }
+
+    /// Continue the counts of an earlier `Events`.
+    pub(crate) fn set_counts(&mut self, counts: Counts) {
+        self.lexer.set_consumed(counts.bytes);
+        self.elements = counts.elements;
+        self.array_elements = counts.array_elements;
+    }
+
+    /// The counts, to continue with `set_counts`.
+    pub(crate) fn counts(&self) -> Counts {
+        Counts {
+            bytes: self.lexer.consumed(),
+            elements: self.elements,
+            array_elements: self.array_elements,
+        }
+    }
+
+    /// Take the tokens that were read ahead, to continue with
+    /// `set_lookahead`.
+    pub(crate) fn take_lookahead(&mut self) -> Vec<Token> {
+        std::mem::take(&mut self.lookahead)
+    }
+
+    /// The `Context` of the last event.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Pass the rest of the events to a `Handler`, until it returns
+    /// `Status::Abort` or the JSON ends.
+    ///
+    /// # Errors
+    ///    - Will return an error Result if the JSON is malformed, and the
+    ///      error wasn't recovered from.
+    pub fn drive<H: Handler + ?Sized>(&mut self, handler: &mut H) -> Result<(), ParseError> {
+        loop {
+            let event = match self.next_event() {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(error) => match &self.recovered {
+                    Some(element_error)
+                        if handler.handle_element_error(&self.context, element_error)
+                            != Status::Abort =>
+                    {
+                        continue
+                    }
+                    _ => return Err(error),
+                },
+            };
+            let status = match (&event, self.keys.as_mut(), self.interned.take()) {
+                (Event::Key(_), Some(keys), Some(id)) => {
+                    let key = keys.key(id).unwrap_or_default();
+                    handler.handle_interned_key(&self.context, id, key)
+                }
+                (Event::Key(key), Some(keys), _) => match keys.intern(key) {
+                    Some(id) => handler.handle_interned_key(&self.context, id, key),
+                    None => handler.handle_map_key(&self.context, key),
+                },
+                _ => event.handle(handler, &self.context),
+            };
+            match status {
+                Status::Abort => break,
+                Status::SkipValue
+                    if matches!(event, Event::Key(_) | Event::StartMap | Event::StartArray) =>
+                {
+                    self.skip_value()?;
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip a value without reading its events, only tracking strings and
+    /// the depth of objects and arrays.
+    ///
+    /// - After a `Event::Key`, the member's value is skipped.
+    /// - After a `Event::StartMap` or `Event::StartArray`, its contents are
+    ///   skipped, and the next event is its `Event::EndMap` or `Event::EndArray`.
+    /// - Before the first event, the whole document is skipped.
+    ///
+    /// Otherwise nothing is skipped.
+    ///
+    /// # Errors
+    ///    - Will return an error Result if the JSON is malformed or ends in the
+    ///      value.
+    pub fn skip_value(&mut self) -> Result<(), ParseError> {
+        match self.pending {
+            Some(Event::StartMap | Event::StartArray) => {
+                self.update();
+                let skipped = self.lexer.skip_to_close(false);
+                self.allow_truncated(skipped)
+            }
+            Some(Event::Key(_)) => self.skip_next_value(),
+            None if self.context.parser_status() == ParserStatus::Start => self.skip_next_value(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Skip the next value, reading its first event but none of the
+    /// events inside it.
+    fn skip_next_value(&mut self) -> Result<(), ParseError> {
+        if let Some(Event::StartMap | Event::StartArray) = self.next_event()? {
+            self.pending = None;
+            let skipped = self.lexer.skip_to_close(true);
+            self.allow_truncated(skipped)?;
+            update_context_status_value(&mut self.context);
+        }
+        Ok(())
+    }
+
+    /// Ignore an error from the JSON ending in a skipped value, with
+    /// `ParserOptions::allow_partial_values`.
+    fn allow_truncated(&mut self, result: Result<(), ParseError>) -> Result<(), ParseError> {
+        match result {
+            Err(_) if self.options.allow_partial_values && self.lexer.truncated() => {
+                self.truncated = true;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// True if the JSON ended in a value, and it was closed because of
+    /// `ParserOptions::allow_partial_values`.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The error of the element that was skipped, if the last error was
+    /// recovered from because of `ParserOptions::recover_elements`.
+    pub fn element_error(&self) -> Option<&ElementError> {
+        self.recovered.as_ref()
+    }
+
+    /// True if the last event was a key, so the next value is its value.
+    #[cfg(feature = "serde")]
+    pub(crate) fn after_key(&self) -> bool {
+        matches!(self.pending, Some(Event::Key(_)))
+    }
+
+    /// The `Context` after the last event that was read.
+    pub(crate) fn into_context(mut self) -> Context {
+        self.update();
+        self.context
+    }
+
+    /// The next event, or None at EOF.
+    ///
+    /// # Errors
+    ///    - Will return an error Result if the JSON is malformed.
+    pub(crate) fn next_event(&mut self) -> Result<Option<Event>, ParseError> {
+        self.recovered = None;
+        match self.read_event() {
+            Err(error) if self.options.recover_elements => Err(self.recover(error)),
+            result => result,
+        }
+    }
+
+    /// Skip to the end of the element of the top-level array that an error
+    /// was found in, if it can be recovered from, and return the error.
+    fn recover(&mut self, error: ParseError) -> ParseError {
+        let context = &mut self.context;
+        let mut depth = match error {
+            ParseError::MalformedJson(_)
+            | ParseError::Utf8Error { .. }
+            | ParseError::StringLengthExceeded(_)
+            | ParseError::NumberLengthExceeded(_)
+            | ParseError::DepthLimitExceeded(_)
+                if !self.lexer.truncated()
+                    && context.first_enclosing() == Some(Enclosing::LeftBracket) =>
+            {
+                context.depth() - 1
+            }
+            _ => return error,
+        };
+        // The object or array that was too deep was consumed.
+        if let ParseError::DepthLimitExceeded(_) = error {
+            depth += 1;
+        }
+        let offset = self.lexer.offset();
+        if self.lexer.skip_to_next_element(depth).is_err() {
+            return error;
+        }
+
+        // The error was in the element that was started, unless it was before
+        // the next one.
+        let next = context.depth() == 1
+            && matches!(
+                context.parser_status(),
+                ParserStatus::ArrayStart | ParserStatus::ArrayNeedVal
+            );
+        let index = if next {
+            self.array_elements += 1;
+            self.array_elements - 1
+        } else {
+            self.array_elements.saturating_sub(1)
+        };
+        while context.depth() > 1 {
+            match context.remove_last_enclosing() {
+                Some(Enclosing::LeftBrace) => context.dec_braces(),
+                _ => context.dec_brackets(),
+            }
+        }
+        context.update_status(ParserStatus::ArrayGotVal);
+        self.pending = None;
+        self.lookahead.clear();
+        self.recovered = Some(ElementError {
+            index,
+            offset,
+            error: error.clone(),
+        });
+        error
+    }
+
+    fn read_event(&mut self) -> Result<Option<Event>, ParseError> {
+        self.update();
+
+        loop {
+            let context = &self.context;
+            if context.parser_status() == ParserStatus::LexicalError {
+                return Err(ParseError::MalformedJson(format!(
+                    "Parse failed due to malformed json: open braces: {}, open brackets: {}",
+                    context.num_open_braces(),
+                    context.num_open_brackets()
+                )));
+            }
+            if context.parser_status() == ParserStatus::ParseComplete {
+                return Ok(None);
+            }
+
+            let key = matches!(
+                context.parser_status(),
+                ParserStatus::MapStart | ParserStatus::MapNeedKey
+            );
+            let unquoted_key = self.options.allow_unquoted_keys && key;
+            let token = self.next_token(key)?;
+            let context = &mut self.context;
+            let event = match token {
+                Some(Token::BracketClose) => {
+                    if context.parser_status() == ParserStatus::ArrayNeedVal
+                        && !self.options.allow_trailing_commas
+                    {
+                        return Err(trailing_comma());
+                    }
+                    if context.last_enclosing() != Some(Enclosing::LeftBracket) {
+                        return Err(ParseError::MalformedJson(format!("Parsed right bracket without a corresponding left bracket: braces: {}, brackets: {}", context.num_open_braces(), context.num_open_brackets())));
+                    }
+                    Event::EndArray
+                }
+                Some(Token::CurlyClose) => {
+                    if context.parser_status() == ParserStatus::MapNeedKey
+                        && !self.options.allow_trailing_commas
+                    {
+                        return Err(trailing_comma());
+                    }
+                    if context.last_enclosing() != Some(Enclosing::LeftBrace) {
+                        return Err(ParseError::MalformedJson(format!("Parsed right brace without a corresponding left brace: braces: {}, brackets: {}", context.num_open_braces(), context.num_open_brackets())));
+                    }
+                    Event::EndMap
+                }
+                Some(Token::BracketOpen) => Event::StartArray,
+                Some(Token::CurlyOpen) => Event::StartMap,
+                Some(Token::Null) if unquoted_key => Event::Key("\"null\"".to_owned()),
+                Some(Token::True) if unquoted_key => Event::Key("\"true\"".to_owned()),
+                Some(Token::False) if unquoted_key => Event::Key("\"false\"".to_owned()),
+                Some(Token::Number(word))
+                    if unquoted_key && (word == "NaN" || word == "Infinity") =>
+                {
+                    Event::Key(format!("\"{}\"", word))
+                }
+                Some(Token::Ident(ident)) if unquoted_key => Event::Key(format!("\"{}\"", ident)),
+                Some(Token::InternedKey(id)) => {
+                    self.interned = Some(id);
+                    Event::Key(String::new())
+                }
+                Some(Token::Ident(ident)) => {
+                    return Err(ParseError::MalformedJson(format!(
+                        "Invalid literal {}",
+                        ident
+                    )))
+                }
+                Some(Token::Null) => Event::Null,
+                Some(Token::Number(s)) => {
+                    if let Ok(num) = s.parse::<i64>() {
+                        Event::Int(num)
+                    } else if let Ok(num) = s.parse::<u64>() {
+                        Event::UInt(num)
+                    } else if let Ok(num) = s.parse::<f64>() {
+                        Event::Double(num)
+                    } else {
+                        return Err(ParseError::MalformedJson(format!(
+                            "Could not parse number as i64 or f64: {}",
+                            s
+                        )));
+                    }
+                }
+                Some(Token::String(string)) => match context.parser_status() {
+                    ParserStatus::ArrayNeedVal
+                    | ParserStatus::ArrayStart
+                    | ParserStatus::MapNeedVal
+                    | ParserStatus::Start => Event::String(string),
+                    ParserStatus::MapNeedKey | ParserStatus::MapStart => Event::Key(string),
+                    _ => {
+                        context.update_status(ParserStatus::LexicalError);
+                        continue;
+                    }
+                },
+                Some(Token::True) => Event::Bool(true),
+                Some(Token::False) => Event::Bool(false),
+                Some(Token::Comma) => {
+                    if context.parser_status() == ParserStatus::MapGotVal {
+                        context.update_status(ParserStatus::MapNeedKey);
+                    } else if context.parser_status() == ParserStatus::ArrayGotVal {
+                        context.update_status(ParserStatus::ArrayNeedVal);
+                    } else {
+                        context.update_status(ParserStatus::LexicalError);
+                    }
+                    continue;
+                }
+                Some(Token::Colon) => {
+                    if context.parser_status() == ParserStatus::MapSep {
+                        context.update_status(ParserStatus::MapNeedVal);
+                    }
+                    continue;
+                }
+                None if self.options.allow_partial_values && context.last_enclosing().is_some() => {
+                    self.truncated = true;
+                    let event = match context.last_enclosing() {
+                        Some(Enclosing::LeftBrace) => Event::EndMap,
+                        _ => Event::EndArray,
+                    };
+                    self.pending = Some(event.clone());
+                    return Ok(Some(event));
+                }
+                // JSON5 requires a value, even if there are comments.
+                None if self.options.json5 && context.parser_status() == ParserStatus::Start => {
+                    return Err(ParseError::MalformedJson(
+                        "Unexpected end of JSON".to_owned(),
+                    ))
+                }
+                None => {
+                    context.update_status(ParserStatus::ParseComplete);
+                    return Ok(None);
+                }
+            };
+
+            if let Event::Key(_) = event {
+                if self.options.allow_partial_values && !self.key_has_value()? {
+                    self.truncated = true;
+                    continue;
+                }
+            }
+            match event {
+                Event::Key(_) | Event::EndMap | Event::EndArray => (),
+                Event::StartMap | Event::StartArray => {
+                    let context = &self.context;
+                    let depth = context.num_open_braces() + context.num_open_brackets();
+                    match self.options.max_depth {
+                        Some(max) if depth >= max => {
+                            return Err(ParseError::DepthLimitExceeded(max))
+                        }
+                        _ => self.count_element()?,
+                    }
+                }
+                _ => self.count_element()?,
+            }
+            let context = &self.context;
+            if context.depth() == 1
+                && context.first_enclosing() == Some(Enclosing::LeftBracket)
+                && matches!(
+                    context.parser_status(),
+                    ParserStatus::ArrayStart | ParserStatus::ArrayNeedVal
+                )
+                && !matches!(event, Event::EndArray)
+            {
+                self.array_elements += 1;
+            }
+            self.pending = Some(event.clone());
+            return Ok(Some(event));
+        }
+    }
+
+    /// The next token, interning a string in the `KeyCache` if it is a
+    /// `key`. With `ParserOptions::allow_partial_values` it is None if it was
+    /// cut off by EOF, which for a number in an object or array is whenever
+    /// it ends at EOF.
+    fn next_token(&mut self, key: bool) -> Result<Option<Token>, ParseError> {
+        if let Some(token) = self.lookahead.pop() {
+            return Ok(Some(token));
+        }
+        let keys = if key { self.keys.as_mut() } else { None };
+        let token = self.lexer.next_token(keys);
+        if !self.options.allow_partial_values || !self.lexer.truncated() {
+            return token;
+        }
+        match token {
+            Err(_) => (),
+            Ok(Some(Token::Number(_))) if self.context.last_enclosing().is_some() => (),
+            token => return token,
+        }
+        self.truncated = true;
+        Ok(None)
+    }
+
+    /// Read ahead to the first token of the value of a key, with
+    /// `ParserOptions::allow_partial_values`. False if the JSON ends first.
+    fn key_has_value(&mut self) -> Result<bool, ParseError> {
+        let mut tokens = vec![];
+        let mut token = self.next_token(false)?;
+        if token == Some(Token::Colon) {
+            tokens.push(Token::Colon);
+            token = self.next_token(false)?;
+        }
+        match token {
+            Some(token) => {
+                tokens.push(token);
+                self.lookahead = tokens.into_iter().rev().collect();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Count a value, checking `max_elements`.
+    fn count_element(&mut self) -> Result<(), ParseError> {
+        self.elements += 1;
+        match self.options.max_elements {
+            Some(max) if self.elements > max => Err(ParseError::ElementCountExceeded(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Update the `Context` for the last event that was read.
+    fn update(&mut self) {
+        let context = &mut self.context;
+        match self.pending.take() {
+            Some(Event::EndArray) => {
+                context.remove_last_enclosing();
+                context.dec_brackets();
+                update_context_status_end(context);
+            }
+            Some(Event::EndMap) => {
+                if context.last_enclosing() == Some(Enclosing::LeftBrace) {
+                    context.remove_last_enclosing();
+                    context.dec_braces();
+                } else {
+                    context.update_status(ParserStatus::LexicalError);
+                }
+                update_context_status_end(context);
+            }
+            Some(Event::StartArray) => {
+                context.add_enclosing(Enclosing::LeftBracket);
+                context.inc_brackets();
+                context.update_status(ParserStatus::ArrayStart);
+            }
+            Some(Event::StartMap) => {
+                context.add_enclosing(Enclosing::LeftBrace);
+                context.inc_braces();
+                context.update_status(ParserStatus::MapStart);
+            }
+            Some(Event::Key(_)) => context.update_status(ParserStatus::MapSep),
+            Some(_) => update_context_status_value(context),
+            None => (),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Events<R> {
+    type Item = Result<Event, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let event = self.next_event().transpose();
+        self.failed = matches!(event, Some(Err(_))) && self.recovered.is_none();
+        event
+    }
+}
+
+fn trailing_comma() -> ParseError {
+    ParseError::MalformedJson("Trailing comma".to_owned())
+}
+
+fn update_context_status_end(context: &mut Context) {
+    if context.last_enclosing() == Some(Enclosing::LeftBrace) {
+        context.update_status(ParserStatus::MapGotVal);
+    } else if context.last_enclosing() == Some(Enclosing::LeftBracket) {
+        context.update_status(ParserStatus::ArrayGotVal);
+    } else {
+        context.update_status(ParserStatus::GotValue);
+    }
+}
+
+fn update_context_status_value(context: &mut Context) {
+    if context.parser_status() == ParserStatus::ArrayNeedVal
+        || context.parser_status() == ParserStatus::ArrayStart
+    {
+        context.update_status(ParserStatus::ArrayGotVal);
+    } else if context.parser_status() == ParserStatus::MapNeedVal {
+        context.update_status(ParserStatus::MapGotVal);
+    } else if context.parser_status() == ParserStatus::Start {
+        context.update_status(ParserStatus::GotValue);
+    } else {
+        context.update_status(ParserStatus::LexicalError);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+    use pretty_assertions::assert_eq;
+
+    type Record = (Event, ParserStatus, usize, usize, Option<Enclosing>);
+
+    fn record(event: Event, ctx: &Context) -> Record {
+        (
+            event,
+            ctx.parser_status(),
+            ctx.num_open_braces(),
+            ctx.num_open_brackets(),
+            ctx.last_enclosing(),
+        )
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<Record>);
+
+    impl Handler for Recorder {
+        fn handle_null(&mut self, ctx: &Context) -> Status {
+            self.0.push(record(Event::Null, ctx));
+            Status::Continue
+        }
+
+        fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+            self.0.push(record(Event::Double(val), ctx));
+            Status::Continue
+        }
+
+        fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+            self.0.push(record(Event::Int(val), ctx));
+            Status::Continue
+        }
+
+        fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+            self.0.push(record(Event::Bool(val), ctx));
+            Status::Continue
+        }
+
+        fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+            self.0.push(record(Event::String(val.to_owned()), ctx));
+            Status::Continue
+        }
+
+        fn handle_start_map(&mut self, ctx: &Context) -> Status {
+            self.0.push(record(Event::StartMap, ctx));
+            Status::Continue
+        }
+
+        fn handle_end_map(&mut self, ctx: &Context) -> Status {
+            self.0.push(record(Event::EndMap, ctx));
+            Status::Continue
+        }
+
+        fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+            self.0.push(record(Event::Key(key.to_owned()), ctx));
+            Status::Continue
+        }
+
+        fn handle_start_array(&mut self, ctx: &Context) -> Status {
+            self.0.push(record(Event::StartArray, ctx));
+            Status::Continue
+        }
+
+        fn handle_end_array(&mut self, ctx: &Context) -> Status {
+            self.0.push(record(Event::EndArray, ctx));
+            Status::Continue
+        }
+    }
+
+    #[test]
+    fn test_events_context() {
+        let input = r#"{ "a": [1, 2.5, { "b": null }], "c": "x", "d": [[true]] }"#;
+
+        let mut recorder = Recorder::default();
+        let mut parser = Parser::new(&mut recorder);
+        parser.parse(&mut input.as_bytes()).unwrap();
+
+        let mut events = Events::new(input.as_bytes());
+        let mut records = vec![];
+        while let Some(event) = events.next() {
+            records.push(record(event.unwrap(), events.context()));
+        }
+
+        assert_eq!(records.len(), 19);
+        assert_eq!(records, recorder.0);
+    }
+
+    #[test]
+    fn test_events_error() {
+        let events: Vec<_> = Events::new("[1 2]".as_bytes()).collect();
+        assert_eq!(events.len(), 4);
+        assert!(events[3].is_err());
+    }
+
+    #[test]
+    fn test_skip_value() {
+        let input = r#"{ "a": { "b": "}", "c": [1] }, "d": [2, [3]], "e": 4 }"#;
+        let mut events = Events::new(input.as_bytes());
+        let mut seen = vec![];
+        while let Some(event) = events.next() {
+            let event = event.unwrap();
+            match event {
+                Event::Key(ref key) if key == "\"a\"" => events.skip_value().unwrap(),
+                Event::StartArray => events.skip_value().unwrap(),
+                _ => (),
+            }
+            seen.push(event);
+        }
+        assert_eq!(
+            seen,
+            vec![
+                Event::StartMap,
+                Event::Key("\"a\"".to_owned()),
+                Event::Key("\"d\"".to_owned()),
+                Event::StartArray,
+                Event::EndArray,
+                Event::Key("\"e\"".to_owned()),
+                Event::Int(4),
+                Event::EndMap,
+            ]
+        );
+        assert_eq!(
+            events.context().parser_status(),
+            ParserStatus::ParseComplete
+        );
+
+        let mut events = Events::new("[1, {\"a\": 2}]".as_bytes());
+        events.skip_value().unwrap();
+        assert!(events.next().is_none());
+
+        // A truncated value is skipped to the end, and closed.
+        let options = ParserOptions::new().allow_partial_values(true);
+        let mut events = Events::new("{ \"a\": [1, { \"b".as_bytes()).options(options);
+        assert_eq!(events.nth(1), Some(Ok(Event::Key("\"a\"".to_owned()))));
+        events.skip_value().unwrap();
+        assert_eq!(events.collect::<Vec<_>>(), vec![Ok(Event::EndMap)]);
+    }
+
+    #[test]
+    fn test_recover_elements() {
+        let input = r#"[1, "a long string", { "b": [2, 1e] }, 4]"#;
+        let options = ParserOptions::new()
+            .max_string_length(3)
+            .recover_elements(true);
+        let mut events = Events::new(input.as_bytes()).options(options);
+        let mut seen = vec![];
+        while let Some(event) = events.next() {
+            seen.push(event.map_err(|_| events.element_error().cloned().unwrap()));
+        }
+        assert_eq!(
+            seen,
+            vec![
+                Ok(Event::StartArray),
+                Ok(Event::Int(1)),
+                Err(ElementError {
+                    index: 1,
+                    offset: 9,
+                    error: ParseError::StringLengthExceeded(3),
+                }),
+                Ok(Event::StartMap),
+                Ok(Event::Key("\"b\"".to_owned())),
+                Ok(Event::StartArray),
+                Ok(Event::Int(2)),
+                Err(ElementError {
+                    index: 2,
+                    offset: 34,
+                    error: ParseError::MalformedJson(
+                        "Could not parse number as i64 or f64: 1e".to_owned()
+                    ),
+                }),
+                Ok(Event::Int(4)),
+                Ok(Event::EndArray),
+            ]
+        );
+        assert_eq!(
+            events.context().parser_status(),
+            ParserStatus::ParseComplete
+        );
+
+        // The rest of a string that was too long is skipped.
+        let mut events = Events::new("['a, long, string', 2]".as_bytes())
+            .options(options.allow_single_quotes(true));
+        let seen: Vec<_> = events.by_ref().filter_map(Result::ok).collect();
+        assert_eq!(
+            seen,
+            vec![Event::StartArray, Event::Int(2), Event::EndArray]
+        );
+
+        // A slash that doesn't start a comment doesn't hide the end of the
+        // element after it.
+        let inputs = [
+            (
+                "[x /, 3]",
+                vec![Event::StartArray, Event::Int(3), Event::EndArray],
+            ),
+            ("[x /]", vec![Event::StartArray, Event::EndArray]),
+        ];
+        for (input, expected) in inputs {
+            let mut events = Events::new(input.as_bytes()).options(options.allow_comments(true));
+            let seen: Vec<_> = events.by_ref().filter_map(Result::ok).collect();
+            assert_eq!(seen, expected, "{}", input);
+        }
+
+        // Only errors in the elements of a top-level array are recovered from.
+        let mut events = Events::new(r#"{ "a": [#, 1] }"#.as_bytes()).options(options);
+        assert_eq!(events.nth(3).map(|e| e.is_err()), Some(true));
+        assert!(events.element_error().is_none());
+        assert!(events.next().is_none());
+    }
+
+    struct Skipper(Vec<Event>);
+
+    impl Handler for Skipper {
+        fn handle_null(&mut self, _ctx: &Context) -> Status {
+            self.0.push(Event::Null);
+            Status::Continue
+        }
+
+        fn handle_double(&mut self, _ctx: &Context, val: f64) -> Status {
+            self.0.push(Event::Double(val));
+            Status::Continue
+        }
+
+        fn handle_int(&mut self, _ctx: &Context, val: i64) -> Status {
+            self.0.push(Event::Int(val));
+            Status::Continue
+        }
+
+        fn handle_bool(&mut self, _ctx: &Context, val: bool) -> Status {
+            self.0.push(Event::Bool(val));
+            Status::Continue
+        }
+
+        fn handle_string(&mut self, _ctx: &Context, val: &str) -> Status {
+            self.0.push(Event::String(val.to_owned()));
+            Status::Continue
+        }
+
+        fn handle_start_map(&mut self, _ctx: &Context) -> Status {
+            self.0.push(Event::StartMap);
+            Status::Continue
+        }
+
+        fn handle_end_map(&mut self, _ctx: &Context) -> Status {
+            self.0.push(Event::EndMap);
+            Status::Continue
+        }
+
+        fn handle_map_key(&mut self, _ctx: &Context, key: &str) -> Status {
+            self.0.push(Event::Key(key.to_owned()));
+            if key == "\"skip\"" {
+                Status::SkipValue
+            } else {
+                Status::Continue
+            }
+        }
+
+        fn handle_start_array(&mut self, _ctx: &Context) -> Status {
+            self.0.push(Event::StartArray);
+            Status::SkipValue
+        }
+
+        fn handle_end_array(&mut self, _ctx: &Context) -> Status {
+            self.0.push(Event::EndArray);
+            Status::Continue
+        }
+    }
+
+    #[test]
+    fn test_handler_skip_value() {
+        let input = r#"{ "skip": [{"x": 1}], "a": ["\"]"], "b": null }"#;
+        let mut skipper = Skipper(vec![]);
+        Parser::new(&mut skipper)
+            .parse(&mut input.as_bytes())
+            .unwrap();
+        assert_eq!(
+            skipper.0,
+            vec![
+                Event::StartMap,
+                Event::Key("\"skip\"".to_owned()),
+                Event::Key("\"a\"".to_owned()),
+                Event::StartArray,
+                Event::EndArray,
+                Event::Key("\"b\"".to_owned()),
+                Event::Null,
+                Event::EndMap,
+            ]
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Interning of object keys, so that handlers can match repeated keys by an
+//! integer instead of comparing strings.
+//!
+//! ```
+//!     use yajlish::{Context, Handler, KeyCache, KeyId, Parser, Status};
+//!
+//!     struct IdCounter {
+//!         id: Option<KeyId>,
+//!         count: usize,
+//!     }
+//!
+//!     impl Handler for IdCounter {
+//!         fn handle_interned_key(&mut self, _ctx: &Context, id: KeyId, _key: &str) -> Status {
+//!             if Some(id) == self.id {
+//!                 self.count += 1;
+//!             }
+//!             Status::Continue
+//!         }
+//!
+//!         fn handle_map_key(&mut self, _ctx: &Context, _key: &str) -> Status { Status::Continue }
+//!         fn handle_null(&mut self, _ctx: &Context) -> Status { Status::Continue }
+//!         fn handle_bool(&mut self, _ctx: &Context, _val: bool) -> Status { Status::Continue }
+//!         fn handle_double(&mut self, _ctx: &Context, _val: f64) -> Status { Status::Continue }
+//!         fn handle_int(&mut self, _ctx: &Context, _val: i64) -> Status { Status::Continue }
+//!         fn handle_string(&mut self, _ctx: &Context, _val: &str) -> Status { Status::Continue }
+//!         fn handle_start_map(&mut self, _ctx: &Context) -> Status { Status::Continue }
+//!         fn handle_end_map(&mut self, _ctx: &Context) -> Status { Status::Continue }
+//!         fn handle_start_array(&mut self, _ctx: &Context) -> Status { Status::Continue }
+//!         fn handle_end_array(&mut self, _ctx: &Context) -> Status { Status::Continue }
+//!     }
+//!
+//!     let mut keys = KeyCache::new();
+//!     let mut handler = IdCounter { id: keys.intern("\"id\""), count: 0 };
+//!     let mut parser = Parser::new(&mut handler).key_cache(keys);
+//!     parser.parse(&mut "[{ \"id\": 1 }, { \"id\": 2, \"x\": 3 }]".as_bytes()).unwrap();
+//!     drop(parser);
+//!
+//!     assert_eq!(handler.count, 2);
+//! ```
+
+use std::collections::HashMap;
+
+/// The default `max_keys`.
+pub const DEFAULT_MAX_KEYS: usize = 4096;
+
+/// The ID of an interned key, see `KeyCache`.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct KeyId(u32);
+
+impl KeyId {
+    /// The IDs of a `KeyCache` count up from 0, in the order the keys were
+    /// interned.
+    #[must_use]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Interned keys, as they are passed to a `Handler` with their quotes and
+/// escapes. Give it to `Parser::key_cache` to have keys passed to
+/// `Handler::handle_interned_key` with their ID.
+///
+/// The parser looks keys up in the cache before allocating them, so a key
+/// that is already interned costs a hash lookup instead of an allocation.
+/// Keys in single quotes, unquoted or in JSON5 are still allocated first.
+///
+/// Only the first `max_keys` distinct keys are interned, so that objects
+/// used as maps don't grow the cache without bound. Other keys are passed
+/// to `Handler::handle_map_key`.
+#[derive(Debug, Clone)]
+pub struct KeyCache {
+    ids: HashMap<String, KeyId>,
+    keys: Vec<String>,
+    max_keys: usize,
+}
+
+impl Default for KeyCache {
+    fn default() -> Self {
+        KeyCache {
+            ids: HashMap::new(),
+            keys: vec![],
+            max_keys: DEFAULT_MAX_KEYS,
+        }
+    }
+}
+
+impl KeyCache {
+    /// Constructor.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of distinct keys to intern, `DEFAULT_MAX_KEYS` by default.
+    #[must_use]
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys.min(u32::MAX as usize);
+        self
+    }
+
+    /// The ID of the key, interning it if it is new. None if it is new and
+    /// there are already `max_keys` keys.
+    pub fn intern(&mut self, key: &str) -> Option<KeyId> {
+        if let Some(id) = self.ids.get(key) {
+            return Some(*id);
+        }
+        if self.keys.len() >= self.max_keys {
+            return None;
+        }
+        let id = KeyId(self.keys.len() as u32);
+        self.ids.insert(key.to_owned(), id);
+        self.keys.push(key.to_owned());
+        Some(id)
+    }
+
+    /// The ID of the key, if it is interned.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<KeyId> {
+        self.ids.get(key).copied()
+    }
+
+    /// The key with the ID.
+    #[must_use]
+    pub fn key(&self, id: KeyId) -> Option<&str> {
+        self.keys.get(id.index()).map(String::as_str)
+    }
+
+    /// The number of interned keys.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// True if no keys are interned.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        combinators::from_fn, value::ValueBuilder, Context, Event, Handler, Parser, ParserOptions,
+        Status,
+    };
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_intern() {
+        let mut keys = KeyCache::new().max_keys(2);
+        let a = keys.intern("\"a\"");
+        assert_eq!(a.map(KeyId::index), Some(0));
+        assert_eq!(keys.intern("\"b\"").map(KeyId::index), Some(1));
+        assert_eq!(keys.intern("\"a\""), a);
+        assert_eq!(keys.intern("\"c\""), None);
+        assert_eq!(keys.get("\"c\""), None);
+        assert_eq!(keys.key(a.unwrap()), Some("\"a\""));
+        assert_eq!(keys.len(), 2);
+    }
+
+    /// Records the ID of each key, or None if it was passed to
+    /// `handle_map_key`.
+    struct Ids<H>(H, Vec<Option<usize>>);
+
+    impl<H: Handler> Handler for Ids<H> {
+        fn handle_null(&mut self, ctx: &Context) -> Status {
+            self.0.handle_null(ctx)
+        }
+
+        fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+            self.0.handle_double(ctx, val)
+        }
+
+        fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+            self.0.handle_int(ctx, val)
+        }
+
+        fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+            self.0.handle_bool(ctx, val)
+        }
+
+        fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+            self.0.handle_string(ctx, val)
+        }
+
+        fn handle_start_map(&mut self, ctx: &Context) -> Status {
+            self.0.handle_start_map(ctx)
+        }
+
+        fn handle_end_map(&mut self, ctx: &Context) -> Status {
+            self.0.handle_end_map(ctx)
+        }
+
+        fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+            self.1.push(None);
+            self.0.handle_map_key(ctx, key)
+        }
+
+        fn handle_interned_key(&mut self, ctx: &Context, id: KeyId, key: &str) -> Status {
+            self.1.push(Some(id.index()));
+            self.0.handle_map_key(ctx, key)
+        }
+
+        fn handle_start_array(&mut self, ctx: &Context) -> Status {
+            self.0.handle_start_array(ctx)
+        }
+
+        fn handle_end_array(&mut self, ctx: &Context) -> Status {
+            self.0.handle_end_array(ctx)
+        }
+    }
+
+    #[test]
+    fn test_parser_key_cache() {
+        let input = r#"[{ "a": 1, "b": { "a": 2 } }, { "c": 3, "a": 4 }]"#;
+
+        let mut keys = vec![];
+        let mut handler = Ids(
+            from_fn(|_ctx, event| {
+                if let Event::Key(key) = event {
+                    keys.push(key);
+                }
+                Status::Continue
+            }),
+            vec![],
+        );
+        let mut parser = Parser::new(&mut handler).key_cache(KeyCache::new().max_keys(2));
+        parser.parse(&mut input.as_bytes()).unwrap();
+        assert_eq!(parser.keys().map(KeyCache::len), Some(2));
+        drop(parser);
+
+        assert_eq!(handler.1, vec![Some(0), Some(1), Some(0), None, Some(0)]);
+        drop(handler);
+        assert_eq!(keys, vec!["\"a\"", "\"b\"", "\"a\"", "\"c\"", "\"a\""]);
+
+        // Without a cache every key goes to handle_map_key.
+        let mut handler = Ids(from_fn(|_ctx, _event| Status::Continue), vec![]);
+        Parser::new(&mut handler)
+            .parse(&mut input.as_bytes())
+            .unwrap();
+        assert_eq!(handler.1, vec![None; 5]);
+    }
+
+    #[test]
+    fn test_key_cache_options() {
+        let parse = |input: &str, options| {
+            let mut handler = Ids(ValueBuilder::default(), vec![]);
+            let mut parser = Parser::new(&mut handler)
+                .key_cache(KeyCache::new())
+                .options(options);
+            parser.parse(&mut input.as_bytes()).unwrap();
+            let keys = parser.keys().map(KeyCache::len);
+            drop(parser);
+            let value = handler.0.value().map(ToString::to_string);
+            (handler.1, keys, value)
+        };
+
+        // Values aren't interned, even when the key's value is read ahead.
+        let partial = ParserOptions::new().allow_partial_values(true);
+        assert_eq!(
+            parse(r#"{"a": "b", "c": {"a": "d"}, "e""#, partial),
+            (
+                vec![Some(0), Some(1), Some(0)],
+                Some(3),
+                Some(r#"{"a":"b","c":{"a":"d"}}"#.to_owned())
+            )
+        );
+
+        // Keys that aren't lexed as plain strings are interned too.
+        assert_eq!(
+            parse("{a: 1, 'b': 2, \"a\": 3}", ParserOptions::json5()),
+            (
+                vec![Some(0), Some(1), Some(0)],
+                Some(2),
+                Some(r#"{"a":1,"b":2,"a":3}"#.to_owned())
+            )
+        );
+    }
+}
@@ -0,0 +1,880 @@
+//! Splits JSON bytes into tokens.
+
+use crate::{
+    encoding::{Decoder, DecoderState, Encoding},
+    keys::{KeyCache, KeyId},
+    parser::{ParseError, ParserOptions},
+};
+use std::io::BufRead;
+
+/// A JSON token. Numbers and strings are as they were in the JSON, strings
+/// with their quotes and escapes.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Token {
+    CurlyOpen,
+    CurlyClose,
+    BracketOpen,
+    BracketClose,
+    Colon,
+    Comma,
+    Null,
+    True,
+    False,
+    Number(String),
+    String(String),
+    /// An unquoted key, with `ParserOptions::allow_unquoted_keys`.
+    Ident(String),
+    /// A key that was interned in a `KeyCache`.
+    InternedKey(KeyId),
+}
+
+pub(crate) struct Lexer<R> {
+    read: Decoder<R>,
+    /// The number of bytes consumed.
+    offset: u64,
+    /// The JSON ended in a token or skipped value.
+    truncated: bool,
+    /// The quote of the string being consumed, if an error was found in it.
+    in_string: Option<u8>,
+    /// The buffer of keys that are interned, which is reused.
+    key_buf: Vec<u8>,
+    options: ParserOptions,
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+fn is_number(b: u8) -> bool {
+    matches!(b, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')
+}
+
+fn is_ident(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$'
+}
+
+/// The length of the whitespace of JSON5 that isn't JSON whitespace at the
+/// start of the bytes, or 0.
+fn json5_space(bytes: &[u8]) -> usize {
+    match bytes {
+        [0x0B | 0x0C, ..] => 1,
+        [0xC2, 0xA0, ..] => 2,
+        [0xE2, 0x80, 0xA8 | 0xA9, ..] | [0xEF, 0xBB, 0xBF, ..] => 3,
+        _ => 0,
+    }
+}
+
+fn unexpected(b: u8) -> ParseError {
+    ParseError::MalformedJson(format!("Unexpected byte {:?}", b as char))
+}
+
+fn invalid_escape(b: u8) -> ParseError {
+    ParseError::MalformedJson(format!("Invalid escape in string at {:?}", b as char))
+}
+
+fn into_string(bytes: Vec<u8>) -> Result<String, ParseError> {
+    String::from_utf8(bytes).map_err(|e| ParseError::MalformedJson(e.to_string()))
+}
+
+impl<R: BufRead> Lexer<R> {
+    pub(crate) fn new(read: R) -> Self {
+        Lexer {
+            read: Decoder::new(read),
+            offset: 0,
+            truncated: false,
+            in_string: None,
+            key_buf: vec![],
+            options: ParserOptions::default(),
+        }
+    }
+
+    pub(crate) fn set_options(&mut self, options: ParserOptions) {
+        self.read.set_replace(options.replace_invalid_utf8);
+        self.options = options;
+    }
+
+    /// The encoding of the input, once it has been detected.
+    pub(crate) fn encoding(&self) -> Option<Encoding> {
+        self.read.encoding()
+    }
+
+    /// Continue the input of an earlier `Lexer`.
+    pub(crate) fn set_decoder_state(&mut self, state: DecoderState) {
+        self.read.set_state(state);
+    }
+
+    /// Take the state of the decoder, to continue with `set_decoder_state`.
+    pub(crate) fn take_decoder_state(&mut self) -> DecoderState {
+        self.read.take_state()
+    }
+
+    /// Continue counting from the bytes consumed by an earlier `Lexer`.
+    pub(crate) fn set_consumed(&mut self, consumed: u64) {
+        self.offset = consumed;
+    }
+
+    /// The number of bytes consumed, not including a byte order mark.
+    pub(crate) fn consumed(&self) -> u64 {
+        self.offset
+    }
+
+    /// The number of bytes read, including a byte order mark. UTF-16 and
+    /// UTF-32 are counted after transcoding to UTF-8.
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset + self.read.bom() as u64
+    }
+
+    /// True if the JSON ended in a token or skipped value.
+    pub(crate) fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Convert the bytes of a string or key, where `offset` gives the offset
+    /// in the input of a byte. Invalid UTF-8 is replaced with
+    /// `ParserOptions::replace_invalid_utf8`.
+    fn utf8<F>(&self, bytes: Vec<u8>, offset: F) -> Result<String, ParseError>
+    where
+        F: FnOnce(usize) -> u64,
+    {
+        match String::from_utf8(bytes) {
+            Ok(string) => Ok(string),
+            Err(e) if self.options.replace_invalid_utf8 => {
+                Ok(String::from_utf8_lossy(e.as_bytes()).into_owned())
+            }
+            Err(e) => {
+                let e = e.utf8_error();
+                Err(ParseError::Utf8Error {
+                    offset: offset(e.valid_up_to()),
+                    message: match e.error_len() {
+                        Some(len) => format!("Invalid UTF-8 sequence of {} bytes", len),
+                        None => "Incomplete UTF-8 sequence".to_owned(),
+                    },
+                })
+            }
+        }
+    }
+
+    fn unterminated(&mut self) -> ParseError {
+        self.truncated = true;
+        ParseError::MalformedJson("Unexpected end of JSON".to_owned())
+    }
+
+    /// Consume `n` bytes of the buffer, checking `max_bytes`.
+    fn consume(&mut self, n: usize) -> Result<(), ParseError> {
+        self.read.consume(n);
+        self.offset += n as u64;
+        match self.options.max_bytes {
+            Some(max) if self.offset > max => Err(ParseError::DocumentSizeExceeded(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The next byte that isn't whitespace or a comment, without consuming
+    /// it.
+    fn peek(&mut self) -> Result<Option<u8>, ParseError> {
+        loop {
+            let buf = self.read.fill_buf()?;
+            let json5 = self.options.json5;
+            match buf.iter().position(|b| !is_whitespace(*b)) {
+                Some(i) => {
+                    let b = buf[i];
+                    let space = if json5 { json5_space(&buf[i..]) } else { 0 };
+                    self.consume(i + space)?;
+                    if space > 0 {
+                        continue;
+                    }
+                    if b == b'/' && self.options.allow_comments {
+                        self.comment()?;
+                        continue;
+                    }
+                    return Ok(Some(b));
+                }
+                None if buf.is_empty() => return Ok(None),
+                None => {
+                    let len = buf.len();
+                    self.consume(len)?;
+                }
+            }
+        }
+    }
+
+    /// Consume a `//` or `/* */` comment, starting at its first slash.
+    fn comment(&mut self) -> Result<(), ParseError> {
+        self.consume(1)?;
+        let block = match self.read.fill_buf()?.first() {
+            Some(b'/') => false,
+            Some(b'*') => true,
+            Some(b) => return Err(unexpected(*b)),
+            None => return Err(unexpected(b'/')),
+        };
+        self.consume(1)?;
+        let mut star = false;
+        loop {
+            let buf = self.read.fill_buf()?;
+            if buf.is_empty() {
+                return if block {
+                    Err(self.unterminated())
+                } else {
+                    Ok(())
+                };
+            }
+            for (i, b) in buf.iter().enumerate() {
+                if (!block && matches!(*b, b'\n' | b'\r')) || (block && star && *b == b'/') {
+                    return self.consume(i + 1);
+                }
+                star = *b == b'*';
+            }
+            let len = buf.len();
+            self.consume(len)?;
+        }
+    }
+
+    /// Consume bytes while `f` is true, stopping once there are more than
+    /// `max`. No more than `max + 1` bytes are copied.
+    fn take_while<F: Fn(u8) -> bool>(&mut self, f: F, max: usize) -> Result<Vec<u8>, ParseError> {
+        let mut bytes = vec![];
+        while bytes.len() <= max {
+            let buf = self.read.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(bytes);
+            }
+            let room = (max - bytes.len()).saturating_add(1);
+            let buf = &buf[..buf.len().min(room)];
+            match buf.iter().position(|b| !f(*b)) {
+                Some(i) => {
+                    bytes.extend_from_slice(&buf[..i]);
+                    self.consume(i)?;
+                    return Ok(bytes);
+                }
+                None => {
+                    bytes.extend_from_slice(buf);
+                    let len = buf.len();
+                    self.consume(len)?;
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Consume a string, starting at its opening quote, checking
+    /// `max_string_length`.
+    fn string(&mut self) -> Result<String, ParseError> {
+        let mut bytes = vec![];
+        let start = self.string_bytes(&mut bytes)?;
+        self.utf8(bytes, |i| start + i as u64)
+    }
+
+    /// Consume a key, starting at its opening quote, and intern it in
+    /// `keys`. The key is only allocated if it is new, or can't be interned.
+    fn key(&mut self, keys: &mut KeyCache) -> Result<Token, ParseError> {
+        let mut bytes = std::mem::take(&mut self.key_buf);
+        bytes.clear();
+        let start = self.string_bytes(&mut bytes)?;
+        match std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|key| keys.intern(key))
+        {
+            Some(id) => {
+                self.key_buf = bytes;
+                Ok(Token::InternedKey(id))
+            }
+            None => self.utf8(bytes, |i| start + i as u64).map(Token::String),
+        }
+    }
+
+    /// Consume a string into `bytes`, starting at its opening quote,
+    /// checking `max_string_length`. Returns the offset of the quote. No
+    /// more than `max_string_length + 1` bytes of the string are copied.
+    fn string_bytes(&mut self, bytes: &mut Vec<u8>) -> Result<u64, ParseError> {
+        let start = self.offset();
+        self.consume(1)?;
+        let max = self.options.max_string_length.unwrap_or(usize::MAX);
+        let too_long = |bytes: &[u8], quotes| bytes.len() - quotes > max;
+        bytes.push(b'"');
+        let mut escape = Escape::None;
+        self.in_string = Some(b'"');
+        loop {
+            let buf = self.read.fill_buf()?;
+            if buf.is_empty() {
+                return Err(self.unterminated());
+            }
+            // The closing quote can come after `max` bytes of the string.
+            let room = max.saturating_sub(bytes.len() - 1).saturating_add(1);
+            let buf = &buf[..buf.len().min(room)];
+            for (i, b) in buf.iter().enumerate() {
+                escape = match (escape, *b) {
+                    (Escape::None, b'\\') => Escape::Backslash,
+                    (Escape::None, b'"') => {
+                        bytes.extend_from_slice(&buf[..=i]);
+                        self.consume(i + 1)?;
+                        self.in_string = None;
+                        if too_long(bytes, 2) {
+                            return Err(ParseError::StringLengthExceeded(max));
+                        }
+                        return Ok(start);
+                    }
+                    (Escape::None, _) => Escape::None,
+                    (Escape::Backslash, b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => {
+                        Escape::None
+                    }
+                    (Escape::Backslash, b'u') => Escape::Hex(4),
+                    (Escape::Hex(1), b) if b.is_ascii_hexdigit() => Escape::None,
+                    (Escape::Hex(n), b) if b.is_ascii_hexdigit() => Escape::Hex(n - 1),
+                    (_, b) => {
+                        self.consume(i)?;
+                        return Err(invalid_escape(b));
+                    }
+                };
+            }
+            bytes.extend_from_slice(buf);
+            let len = buf.len();
+            self.consume(len)?;
+            if too_long(bytes, 1) {
+                return Err(ParseError::StringLengthExceeded(max));
+            }
+        }
+    }
+
+    /// Consume a string in single quotes, or any string in JSON5, starting
+    /// at its opening quote, and return it in double quotes with the escapes
+    /// of JSON.
+    fn converted_string(&mut self, quote: u8) -> Result<String, ParseError> {
+        self.consume(1)?;
+        let json5 = self.options.json5;
+        let max = self.options.max_string_length.unwrap_or(usize::MAX);
+        let mut bytes = vec![b'"'];
+        // Where non-ASCII bytes are in the input, since the escapes change
+        // the length: the index of a byte and its offset, whenever the
+        // difference changes.
+        let mut offsets: Vec<(usize, u64)> = vec![];
+        let offset = |offsets: &[(usize, u64)], i: usize| {
+            offsets
+                .iter()
+                .rev()
+                .find(|(j, _)| *j <= i)
+                .map_or(0, |(j, offset)| offset + (i - j) as u64)
+        };
+        let mut len = 0;
+        let mut escape = Escape::None;
+        self.in_string = Some(quote);
+        loop {
+            let b = match self.read.fill_buf()?.first() {
+                Some(b) => *b,
+                None => return Err(self.unterminated()),
+            };
+            self.consume(1)?;
+            if b == quote && escape == Escape::None {
+                bytes.push(b'"');
+                self.in_string = None;
+                return self.utf8(bytes, |i| offset(&offsets, i));
+            }
+            len += 1;
+            if len > max {
+                return Err(ParseError::StringLengthExceeded(max));
+            }
+            escape = match (escape, b) {
+                (Escape::None, b'\\') => Escape::Backslash,
+                (Escape::None, b'\n' | b'\r') | (Escape::CarriageReturn, b'\r') if json5 => {
+                    return Err(ParseError::MalformedJson(
+                        "Unescaped line terminator in string".to_owned(),
+                    ))
+                }
+                (Escape::None, b'"') => {
+                    bytes.extend_from_slice(b"\\\"");
+                    Escape::None
+                }
+                (Escape::None, _) | (Escape::Backslash, b'\'') => {
+                    bytes.push(b);
+                    Escape::None
+                }
+                (Escape::Backslash, b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => {
+                    bytes.extend_from_slice(&[b'\\', b]);
+                    Escape::None
+                }
+                (Escape::Backslash, b'u') => {
+                    bytes.extend_from_slice(b"\\u");
+                    Escape::Hex(4)
+                }
+                (Escape::Backslash, _) if !json5 => return Err(invalid_escape(b)),
+                // A line continuation.
+                (Escape::Backslash, b'\n') | (Escape::CarriageReturn, b'\n') => Escape::None,
+                (Escape::Backslash, b'\r') => Escape::CarriageReturn,
+                (Escape::CarriageReturn, b'\\') => Escape::Backslash,
+                (Escape::CarriageReturn, _) if b == quote => {
+                    bytes.push(b'"');
+                    self.in_string = None;
+                    return self.utf8(bytes, |i| offset(&offsets, i));
+                }
+                (Escape::CarriageReturn, b'"') => {
+                    bytes.extend_from_slice(b"\\\"");
+                    Escape::None
+                }
+                (Escape::CarriageReturn, _) => {
+                    bytes.push(b);
+                    Escape::None
+                }
+                (Escape::Backslash, b'v') => {
+                    bytes.extend_from_slice(b"\\u000b");
+                    Escape::None
+                }
+                (Escape::Backslash, b'0') => {
+                    bytes.extend_from_slice(b"\\u0000");
+                    Escape::None
+                }
+                (Escape::Backslash, b'x') => {
+                    bytes.extend_from_slice(b"\\u00");
+                    Escape::Hex(2)
+                }
+                (Escape::Hex(n), b) if b.is_ascii_hexdigit() => {
+                    bytes.push(b);
+                    if n == 1 {
+                        Escape::None
+                    } else {
+                        Escape::Hex(n - 1)
+                    }
+                }
+                (Escape::Hex(_), b) => return Err(invalid_escape(b)),
+                // Any other character stands for itself.
+                (Escape::Backslash, _) => {
+                    bytes.push(b);
+                    Escape::None
+                }
+            };
+            if b >= 0x80 {
+                let i = bytes.len() - 1;
+                let at = self.offset() - 1;
+                if offset(&offsets, i) != at || offsets.is_empty() {
+                    offsets.push((i, at));
+                }
+            }
+        }
+    }
+
+    /// Note a number that ends at EOF as truncated, since more of it may
+    /// have been cut off.
+    fn check_number_end(&mut self) -> Result<(), ParseError> {
+        if self.read.fill_buf()?.is_empty() {
+            self.truncated = true;
+        }
+        Ok(())
+    }
+
+    /// Consume a word, which is a literal or an unquoted key.
+    fn word(&mut self) -> Result<Token, ParseError> {
+        let options = self.options;
+        // Longer than any literal, so a long word isn't buffered.
+        let max = if options.allow_unquoted_keys {
+            options.max_string_length.unwrap_or(usize::MAX)
+        } else {
+            8
+        };
+        let word = if options.json5 {
+            self.take_while(|b| is_ident(b) || b == b'\\' || b >= 0x80, max)?
+        } else {
+            self.take_while(is_ident, max)?
+        };
+        let at_eof = self.read.fill_buf()?.is_empty();
+        match word.as_slice() {
+            b"null" => Ok(Token::Null),
+            b"true" => Ok(Token::True),
+            b"false" => Ok(Token::False),
+            b"NaN" | b"Infinity" if options.allow_nan_infinity => {
+                into_string(word).map(Token::Number)
+            }
+            _ if at_eof
+                && [&b"null"[..], b"true", b"false", b"NaN", b"Infinity"]
+                    .iter()
+                    .any(|literal| literal.starts_with(&word)) =>
+            {
+                Err(self.unterminated())
+            }
+            _ if options.allow_unquoted_keys && word.len() > max => {
+                Err(ParseError::StringLengthExceeded(max))
+            }
+            _ if options.allow_unquoted_keys => {
+                let start = self.offset() - word.len() as u64;
+                self.utf8(word, |i| start + i as u64).map(Token::Ident)
+            }
+            _ => Err(ParseError::MalformedJson(format!(
+                "Invalid literal {}",
+                String::from_utf8_lossy(&word)
+            ))),
+        }
+    }
+
+    /// Consume a JSON5 number, and return it as Rust would parse it. Hex
+    /// numbers are returned in decimal.
+    fn json5_number(&mut self) -> Result<Token, ParseError> {
+        let max = self.options.max_number_length.unwrap_or(usize::MAX);
+        let number = self.take_while(
+            |b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'-' | b'.'),
+            max,
+        )?;
+        self.check_number_end()?;
+        if number.len() > max {
+            return Err(ParseError::NumberLengthExceeded(max));
+        }
+        let number = into_string(number)?;
+        let invalid = || ParseError::MalformedJson(format!("Invalid number {}", number));
+        let (sign, rest) = match number.as_bytes().first() {
+            Some(b'-') => ("-", &number[1..]),
+            Some(b'+') => ("", &number[1..]),
+            _ => ("", &number[..]),
+        };
+        let token = match rest {
+            "Infinity" => Token::Number(format!("{}Infinity", sign)),
+            "NaN" => Token::Number("NaN".to_owned()),
+            _ if rest.starts_with("0x") || rest.starts_with("0X") => {
+                let hex = u64::from_str_radix(&rest[2..], 16).map_err(|_| invalid())?;
+                Token::Number(format!("{}{}", sign, hex))
+            }
+            // A leading zero is only allowed before a decimal point or exponent.
+            _ if rest.len() > 1 && rest.starts_with('0') && rest.as_bytes()[1].is_ascii_digit() => {
+                return Err(invalid())
+            }
+            _ if rest.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+                && rest.bytes().any(|b| b.is_ascii_digit())
+                && rest.parse::<f64>().is_ok() =>
+            {
+                Token::Number(format!("{}{}", sign, rest))
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(token)
+    }
+
+    /// The next token, or None at EOF. A string is interned as a key if
+    /// there are `keys`.
+    pub(crate) fn next_token(
+        &mut self,
+        keys: Option<&mut KeyCache>,
+    ) -> Result<Option<Token>, ParseError> {
+        let b = match self.peek()? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let token = match b {
+            b'{' => Token::CurlyOpen,
+            b'}' => Token::CurlyClose,
+            b'[' => Token::BracketOpen,
+            b']' => Token::BracketClose,
+            b':' => Token::Colon,
+            b',' => Token::Comma,
+            b'"' | b'\'' if self.options.json5 => {
+                return self.converted_string(b).map(|s| Some(Token::String(s)))
+            }
+            b'-' | b'+' | b'.' | b'0'..=b'9' if self.options.json5 => {
+                return self.json5_number().map(Some)
+            }
+            b'"' => {
+                return match keys {
+                    Some(keys) => self.key(keys).map(Some),
+                    None => self.string().map(|s| Some(Token::String(s))),
+                }
+            }
+            b'-' | b'0'..=b'9' => {
+                let max = self.options.max_number_length.unwrap_or(usize::MAX);
+                let number = self.take_while(is_number, max)?;
+                self.check_number_end()?;
+                if number == b"-" && self.options.allow_nan_infinity {
+                    let word = self.take_while(is_ident, 8)?;
+                    self.check_number_end()?;
+                    return match word.as_slice() {
+                        b"Infinity" => Ok(Some(Token::Number("-Infinity".to_owned()))),
+                        _ => Err(ParseError::MalformedJson("Invalid number -".to_owned())),
+                    };
+                }
+                if number.len() > max {
+                    return Err(ParseError::NumberLengthExceeded(max));
+                }
+                return into_string(number).map(|s| Some(Token::Number(s)));
+            }
+            b'\'' if self.options.allow_single_quotes => {
+                return self.converted_string(b).map(|s| Some(Token::String(s)))
+            }
+            b'a'..=b'z' | b'A'..=b'Z' => return self.word().map(Some),
+            b'_' | b'$' if self.options.allow_unquoted_keys => return self.word().map(Some),
+            b'\\' | 0x80..=0xFF if self.options.json5 => return self.word().map(Some),
+            b => return Err(unexpected(b)),
+        };
+        self.consume(1)?;
+        Ok(Some(token))
+    }
+
+    /// Consume bytes up to the close of the object or array that is open,
+    /// only tracking strings, comments and the depth of nested objects and
+    /// arrays. The close is consumed if `consume_close` is true.
+    pub(crate) fn skip_to_close(&mut self, consume_close: bool) -> Result<(), ParseError> {
+        self.skip_until(Skip::Value, 0, false, consume_close)
+    }
+
+    /// Consume bytes up to the `,` or `]` after an element of an array, from
+    /// inside of `depth` objects and arrays in the element, after an error
+    /// was found in it.
+    pub(crate) fn skip_to_next_element(&mut self, depth: usize) -> Result<(), ParseError> {
+        let state = self.in_string.take().map_or(Skip::Value, Skip::String);
+        self.skip_until(state, depth, true, false)
+    }
+
+    fn skip_until(
+        &mut self,
+        mut state: Skip,
+        mut depth: usize,
+        element: bool,
+        consume_close: bool,
+    ) -> Result<(), ParseError> {
+        let options = self.options;
+        let mut escape = false;
+        loop {
+            let buf = self.read.fill_buf()?;
+            if buf.is_empty() {
+                return Err(self.unterminated());
+            }
+            for (i, b) in buf.iter().enumerate() {
+                // A slash in an element that doesn't start a comment is
+                // skipped, and the byte after it is part of the value.
+                if matches!(state, Skip::Slash) && element && !matches!(b, b'/' | b'*') {
+                    state = Skip::Value;
+                }
+                state = match (state, *b) {
+                    (Skip::String(_), _) if escape => {
+                        escape = false;
+                        state
+                    }
+                    (Skip::String(_), b'\\') => {
+                        escape = true;
+                        state
+                    }
+                    (Skip::String(quote), b) if b == quote => Skip::Value,
+                    (Skip::Slash, b'/') | (Skip::Line, _) if !matches!(*b, b'\n' | b'\r') => {
+                        Skip::Line
+                    }
+                    (Skip::Line, _) => Skip::Value,
+                    (Skip::Slash, b'*') => Skip::Block,
+                    (Skip::Slash, b) => return Err(unexpected(b)),
+                    (Skip::Block | Skip::BlockStar, b'*') => Skip::BlockStar,
+                    (Skip::BlockStar, b'/') => Skip::Value,
+                    (Skip::Block | Skip::BlockStar, _) => Skip::Block,
+                    (Skip::String(_), _) => state,
+                    (Skip::Value, b'"') => Skip::String(b'"'),
+                    (Skip::Value, b'\'') if options.allow_single_quotes => Skip::String(b'\''),
+                    (Skip::Value, b'/') if options.allow_comments => Skip::Slash,
+                    (Skip::Value, b'{' | b'[') => {
+                        depth += 1;
+                        state
+                    }
+                    (Skip::Value, b',' | b']') if element && depth == 0 => return self.consume(i),
+                    // A stray close in an element.
+                    (Skip::Value, b'}') if element && depth == 0 => state,
+                    (Skip::Value, b'}' | b']') if depth == 0 => {
+                        return self.consume(if consume_close { i + 1 } else { i });
+                    }
+                    (Skip::Value, b'}' | b']') => {
+                        depth -= 1;
+                        state
+                    }
+                    (Skip::Value, _) => state,
+                };
+            }
+            let len = buf.len();
+            self.consume(len)?;
+        }
+    }
+}
+
+/// Where a string is in an escape.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Backslash,
+    /// After a line continuation with a carriage return, which can be
+    /// followed by a newline.
+    CarriageReturn,
+    /// The number of hex digits left in a `\x` or `\u` escape.
+    Hex(u8),
+}
+
+/// Where `skip_to_close` is.
+#[derive(Debug, Clone, Copy)]
+enum Skip {
+    Value,
+    /// In a string with the quote.
+    String(u8),
+    /// After a slash that starts a comment.
+    Slash,
+    /// In a `//` comment.
+    Line,
+    /// In a `/* */` comment.
+    Block,
+    /// In a `/* */` comment after a star.
+    BlockStar,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::BufReader;
+
+    fn tokens(input: &str) -> Result<Vec<Token>, ParseError> {
+        tokens_with_options(input, ParserOptions::default())
+    }
+
+    fn tokens_with_options(input: &str, options: ParserOptions) -> Result<Vec<Token>, ParseError> {
+        // A small buffer so tokens are split across reads.
+        let mut lexer = Lexer::new(BufReader::with_capacity(2, input.as_bytes()));
+        lexer.set_options(options);
+        let mut tokens = vec![];
+        while let Some(token) = lexer.next_token(None)? {
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            tokens("{ \"a\\\"b\" :[-1.5e3, true,false , null]}\n"),
+            Ok(vec![
+                Token::CurlyOpen,
+                Token::String("\"a\\\"b\"".to_owned()),
+                Token::Colon,
+                Token::BracketOpen,
+                Token::Number("-1.5e3".to_owned()),
+                Token::Comma,
+                Token::True,
+                Token::Comma,
+                Token::False,
+                Token::Comma,
+                Token::Null,
+                Token::BracketClose,
+                Token::CurlyClose,
+            ])
+        );
+        assert!(tokens("[nul]").is_err());
+        assert!(tokens("[\"abc").is_err());
+        assert!(tokens("[#]").is_err());
+    }
+
+    #[test]
+    fn test_skip_to_close() {
+        let mut lexer = Lexer::new(BufReader::with_capacity(
+            3,
+            "{ \"a\": [\"]}\\\"\", {}] }, 1]".as_bytes(),
+        ));
+        lexer.skip_to_close(false).unwrap();
+        assert_eq!(lexer.next_token(None), Ok(Some(Token::BracketClose)));
+
+        let mut lexer = Lexer::new("1, [2]], 3]".as_bytes());
+        lexer.skip_to_close(true).unwrap();
+        assert_eq!(lexer.next_token(None), Ok(Some(Token::Comma)));
+        assert_eq!(
+            lexer.next_token(None),
+            Ok(Some(Token::Number("3".to_owned())))
+        );
+    }
+
+    #[test]
+    fn test_lenient_tokens() {
+        let options = ParserOptions::default()
+            .allow_comments(true)
+            .allow_single_quotes(true)
+            .allow_unquoted_keys(true)
+            .allow_nan_infinity(true);
+        assert_eq!(
+            tokens_with_options(
+                "// a\n{ /* b * / */ $a_1: 'it\\'s \"x\"\\n', NaN -Infinity }",
+                options
+            ),
+            Ok(vec![
+                Token::CurlyOpen,
+                Token::Ident("$a_1".to_owned()),
+                Token::Colon,
+                Token::String("\"it's \\\"x\\\"\\n\"".to_owned()),
+                Token::Comma,
+                Token::Number("NaN".to_owned()),
+                Token::Number("-Infinity".to_owned()),
+                Token::CurlyClose,
+            ])
+        );
+        assert!(tokens_with_options("/* a", options).is_err());
+        assert!(tokens("// a\n1").is_err());
+        assert!(tokens("'a'").is_err());
+        assert!(tokens("NaN").is_err());
+        assert!(tokens("[a]").is_err());
+
+        let mut lexer = Lexer::new("/* ] */ '\\']', // ]\n \"]\" ], 1".as_bytes());
+        lexer.set_options(options);
+        lexer.skip_to_close(true).unwrap();
+        assert_eq!(lexer.next_token(None), Ok(Some(Token::Comma)));
+    }
+
+    #[test]
+    fn test_escapes() {
+        let valid = r#""\"\\\/\b\f\n\r\té😀é""#;
+        assert_eq!(tokens(valid), Ok(vec![Token::String(valid.to_owned())]));
+        for (input, b) in &[
+            (r#""a\qb""#, b'q'),
+            (r#""\u12""#, b'"'),
+            (r#""\u12G4""#, b'G'),
+            (r#""\x41""#, b'x'),
+        ] {
+            assert_eq!(tokens(input), Err(invalid_escape(*b)), "{}", input);
+        }
+
+        let options = ParserOptions::new().allow_single_quotes(true);
+        assert!(tokens_with_options(r"'a\qb'", options).is_err());
+        assert!(tokens_with_options(r"'\u12'", options).is_err());
+        assert_eq!(
+            tokens_with_options(r"'A\''", options),
+            Ok(vec![Token::String(r#""A'""#.to_owned())])
+        );
+        // In JSON5 any other character stands for itself.
+        assert_eq!(
+            tokens_with_options(r"'a\qb'", ParserOptions::json5()),
+            Ok(vec![Token::String(r#""aqb""#.to_owned())])
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let string = |input: &[u8], options: ParserOptions| {
+            let mut lexer = Lexer::new(BufReader::with_capacity(2, input));
+            lexer.set_options(options);
+            lexer.next_token(None)
+        };
+        let error = |offset, message: &str| {
+            Err(ParseError::Utf8Error {
+                offset,
+                message: message.to_owned(),
+            })
+        };
+        let options = ParserOptions::default();
+        assert_eq!(
+            string(b"  \"ab\xFFc\"", options),
+            error(5, "Invalid UTF-8 sequence of 1 bytes")
+        );
+        assert_eq!(
+            string(b"\xEF\xBB\xBF\"\xC3\xA9\xE2\x82\"", options),
+            error(6, "Invalid UTF-8 sequence of 2 bytes")
+        );
+        // Escapes don't change the offset.
+        assert_eq!(
+            string(
+                b"'\\u0041\\'\xC3\xA9\xC3'",
+                options.allow_single_quotes(true)
+            ),
+            error(11, "Invalid UTF-8 sequence of 1 bytes")
+        );
+        assert_eq!(
+            string(b" \xC3\xA9\xE2\x82", ParserOptions::json5()),
+            error(3, "Incomplete UTF-8 sequence")
+        );
+
+        let options = options.replace_invalid_utf8(true);
+        assert_eq!(
+            string(b"\"a\xFF\xC3\"", options),
+            Ok(Some(Token::String("\"a\u{FFFD}\u{FFFD}\"".to_owned())))
+        );
+    }
+}
@@ -71,10 +71,31 @@
 //!     }
 //! ```
 
+pub mod combinators;
 mod common;
+mod compression;
+#[cfg(feature = "ndjson")]
+pub mod csv_handler;
+#[cfg(feature = "serde")]
+pub mod de;
+mod encoding;
+mod events;
+pub mod keys;
+mod lexer;
 #[cfg(feature = "ndjson")]
 pub mod ndjson_handler;
 mod parser;
+#[cfg(feature = "ndjson")]
+pub mod predicate;
+pub mod rotating_writer;
+pub mod schema_handler;
+#[cfg(feature = "validation")]
+pub mod validation_handler;
+pub mod value;
 
 pub use common::{Context, Enclosing, Handler, ParserStatus, Status};
-pub use parser::Parser;
+pub use compression::{decompress, open, Compression};
+pub use encoding::Encoding;
+pub use events::{Event, Events};
+pub use keys::{KeyCache, KeyId};
+pub use parser::{ElementError, ParseError, Parser, ParserOptions};
@@ -1,118 +1,557 @@
 //! This Handler implementation converts JSON
 //! with an array into new-line delimited JSON.
+//! An object can be written one member per line with [`Route::entries`].
+//! Control characters in strings and keys are escaped, so that a newline
+//! is always the end of a line.
 //!
 //! Requires feature `ndjson`.
 //!
 
-use crate::{common::ParserStatus, Context, Handler, Status};
+use crate::{
+    common::ParserStatus,
+    predicate::{Comparison, Escaped, Literal, Predicate, Scalar},
+    value::Value,
+    Context, ElementError, Enclosing, Handler, Status,
+};
 use std::io::Write;
 
 /// Parser.
 pub struct NdJsonHandler<OUT> {
+    routes: Vec<Route<OUT>>,
+}
+
+/// A selector path and the writer that the selected values are written to.
+pub struct Route<OUT> {
     out: OUT,
+    entries: Option<ObjectEntries>,
+    projection: Option<Projection>,
+    filter: Option<Filter>,
+    rejects: Option<OUT>,
+    /// The line being written, if it is held until it is finished.
+    line: Option<Vec<u8>>,
+    /// There are no selectors, so the lines are the elements of the
+    /// top-level array.
+    whole: bool,
 
     context: NdjsonContext,
 }
 
+/// How the members of a selected JSON object are written, one per line.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ObjectEntries {
+    /// Each member is written as `{"key":<key>,"value":<value>}`.
+    KeyValue,
+    /// Each member is written as `[<key>,<value>]`.
+    Pair,
+}
+
 #[derive(Debug)]
 enum NdjsonContext {
     Selecting(Select),
     Writing(WritingCtx),
+    Done,
+}
+
+/// What the selected value turned out to be.
+#[derive(Debug, Clone, Copy)]
+enum Root {
+    /// An array, each element is written on its own line.
+    Elements,
+    /// An object, each member is written on its own line.
+    Entries(ObjectEntries),
+    /// Any other value, written on a single line.
+    Value,
+}
+
+/// Which parts of each line are written. Paths are relative to the line.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Projection {
+    /// Only write the values at these paths, along with the objects
+    /// and arrays that contain them.
+    Include(Vec<Vec<Selector>>),
+    /// Write everything except the values at these paths.
+    Exclude(Vec<Vec<Selector>>),
 }
 
 #[derive(Debug)]
 struct WritingCtx {
-    num_open_braces: usize,
-    num_open_brackets: usize,
-    inside_array: bool,
-    first: bool,
+    root: Option<Root>,
+    /// One entry for each array or object opened inside of a line.
+    stack: Vec<Level>,
+    after_key: bool,
+    /// The value after the most recent key is left out by the projection.
+    skip_value: bool,
+}
+
+#[derive(Debug)]
+struct Level {
+    location: Location,
+    /// True if this array or object is left out by the projection.
+    skipped: bool,
+    /// True once a value has been written to this array or object.
+    has_values: bool,
+}
+
+/// Where the current value is inside of an array or object.
+#[derive(Debug)]
+pub(crate) struct Location {
+    pub(crate) enclosing: Enclosing,
+    /// The most recent key, if this is an object.
+    pub(crate) key: String,
+    /// The number of values started, if this is an array.
+    pub(crate) count: usize,
+}
+
+impl Location {
+    pub(crate) fn new(enclosing: Enclosing) -> Self {
+        Location {
+            enclosing,
+            key: String::new(),
+            count: 0,
+        }
+    }
+
+    /// A value is starting in this array or object.
+    pub(crate) fn enter_value(&mut self) {
+        if self.enclosing == Enclosing::LeftBracket {
+            self.count += 1;
+        }
+    }
+
+    pub(crate) fn set_key(&mut self, key: &str) {
+        self.key.clear();
+        self.key.push_str(key);
+    }
+
+    pub(crate) fn matches(&self, selector: &Selector) -> bool {
+        match (self.enclosing, selector) {
+            (Enclosing::LeftBrace, Selector::Identifier(_)) => selector.matches_key(&self.key),
+            (Enclosing::LeftBracket, Selector::Index(index)) => self.count == index + 1,
+            _ => false,
+        }
+    }
 }
 
 impl WritingCtx {
-    fn new(braces: usize, brackets: usize) -> Self {
+    fn new() -> Self {
         WritingCtx {
-            num_open_braces: braces,
-            num_open_brackets: brackets,
-            inside_array: false,
-            first: true,
+            root: None,
+            stack: vec![],
+            after_key: false,
+            skip_value: false,
         }
     }
 
-    fn first_context() -> Self {
-        WritingCtx {
-            num_open_braces: 0,
-            num_open_brackets: 1,
-            inside_array: false,
-            first: true,
+    /// A line has been started and not yet finished.
+    fn in_line(&self) -> bool {
+        !self.stack.is_empty() || self.after_key
+    }
+
+    /// A value is starting inside of a line.
+    fn enter_value(&mut self) {
+        if let Some(level) = self.stack.last_mut() {
+            level.location.enter_value();
+        }
+    }
+
+    /// The path, relative to the line, of the current value is the path
+    /// of selectors.
+    fn path_matches(&self, path: &[Selector]) -> bool {
+        self.stack.len() == path.len()
+            && self
+                .stack
+                .iter()
+                .zip(path)
+                .all(|(level, selector)| level.location.matches(selector))
+    }
+
+    /// The current value is written by the projection, either because it is
+    /// inside of a path or it contains a path.
+    fn is_projected(&self, projection: &Projection) -> bool {
+        let on_path = |path: &Vec<Selector>| {
+            self.stack
+                .iter()
+                .zip(path)
+                .all(|(level, selector)| level.location.matches(selector))
+        };
+        match projection {
+            Projection::Include(paths) => paths.iter().any(on_path),
+            Projection::Exclude(paths) => !paths
+                .iter()
+                .any(|path| path.len() <= self.stack.len() && on_path(path)),
+        }
+    }
+
+    /// The current value is left out of the line. The line itself is
+    /// always written.
+    fn is_skipped(&mut self, projection: Option<&Projection>) -> bool {
+        if self.after_key {
+            if self.skip_value {
+                self.after_key = false;
+                self.skip_value = false;
+                return true;
+            }
+            return false;
+        }
+        match self.stack.last() {
+            Some(level) if level.skipped => true,
+            Some(_) => projection.is_some_and(|p| !self.is_projected(p)),
+            None => false,
+        }
+    }
+
+    /// Write a comma if this value is not the first in its array or object.
+    fn separate<OUT: Write>(&mut self, out: &mut OUT) {
+        if self.after_key {
+            self.after_key = false;
+        } else if let Some(level) = self.stack.last_mut() {
+            if level.has_values {
+                out.write_all(b",").expect("Unable to write");
+            }
+            level.has_values = true;
+        }
+    }
+
+    /// Finish a line, returning true if nothing else will be written.
+    fn end_line<OUT: Write>(&mut self, out: &mut OUT) -> bool {
+        match self.root {
+            Some(Root::Entries(ObjectEntries::KeyValue)) => {
+                out.write_all(b"}\n").expect("Unable to write")
+            }
+            Some(Root::Entries(ObjectEntries::Pair)) => {
+                out.write_all(b"]\n").expect("Unable to write")
+            }
+            _ => out.write_all(b"\n").expect("Unable to write"),
+        }
+        matches!(self.root, Some(Root::Value))
+    }
+
+    fn value<OUT: Write>(
+        &mut self,
+        out: &mut OUT,
+        val: Scalar<'_>,
+        projection: Option<&Projection>,
+    ) -> bool {
+        if self.root.is_none() {
+            self.root = Some(Root::Value);
+        }
+        if self.is_skipped(projection) {
+            return false;
+        }
+        self.separate(out);
+        write!(out, "{}", val).expect("Unable to write");
+        self.stack.is_empty() && self.end_line(out)
+    }
+
+    fn map_key<OUT: Write>(&mut self, out: &mut OUT, key: &str, projection: Option<&Projection>) {
+        if let Some(level) = self.stack.last_mut() {
+            level.location.set_key(key);
+
+            if self.is_skipped(projection) {
+                self.skip_value = true;
+            } else {
+                self.separate(out);
+                write!(out, "{}:", Escaped(key)).expect("Unable to write");
+            }
+        } else {
+            match self.root {
+                Some(Root::Entries(ObjectEntries::KeyValue)) => {
+                    write!(out, "{{\"key\":{},\"value\":", Escaped(key)).expect("Unable to write")
+                }
+                _ => write!(out, "[{},", Escaped(key)).expect("Unable to write"),
+            }
+        }
+        self.after_key = true;
+    }
+
+    fn start<OUT: Write>(
+        &mut self,
+        out: &mut OUT,
+        enclosing: Enclosing,
+        entries: Option<ObjectEntries>,
+        projection: Option<&Projection>,
+    ) {
+        if self.root.is_none() {
+            match (enclosing, entries) {
+                (Enclosing::LeftBracket, _) => {
+                    self.root = Some(Root::Elements);
+                    return;
+                }
+                (Enclosing::LeftBrace, Some(entries)) => {
+                    self.root = Some(Root::Entries(entries));
+                    return;
+                }
+                (Enclosing::LeftBrace, None) => self.root = Some(Root::Value),
+            }
+        }
+        let skipped = self.is_skipped(projection);
+        if !skipped {
+            self.separate(out);
+            match enclosing {
+                Enclosing::LeftBrace => out.write_all(b"{").expect("Unable to write"),
+                Enclosing::LeftBracket => out.write_all(b"[").expect("Unable to write"),
+            }
+        }
+        self.stack.push(Level {
+            location: Location::new(enclosing),
+            skipped,
+            has_values: false,
+        });
+    }
+
+    fn end<OUT: Write>(&mut self, out: &mut OUT, enclosing: Enclosing) -> bool {
+        match self.stack.pop() {
+            None => return true,
+            Some(level) if level.skipped => return false,
+            Some(_) => (),
+        }
+        match enclosing {
+            Enclosing::LeftBrace => out.write_all(b"}").expect("Unable to write"),
+            Enclosing::LeftBracket => out.write_all(b"]").expect("Unable to write"),
+        }
+        self.stack.is_empty() && self.end_line(out)
+    }
+}
+
+/// Decides whether each line is written, holding on to the line
+/// until the predicate can be evaluated.
+#[derive(Debug)]
+struct Filter {
+    predicate: Predicate,
+    comparisons: Vec<(Vec<Selector>, Comparison, Literal)>,
+    results: Vec<Option<bool>>,
+    decision: Option<bool>,
+    buffer: Vec<u8>,
+}
+
+impl Filter {
+    fn new(predicate: Predicate) -> Self {
+        let comparisons: Vec<_> = predicate
+            .comparisons()
+            .into_iter()
+            .map(|(path, comparison, literal)| (path.to_vec(), comparison, literal.clone()))
+            .collect();
+        Filter {
+            results: vec![None; comparisons.len()],
+            predicate,
+            comparisons,
+            decision: None,
+            buffer: vec![],
+        }
+    }
+
+    fn start_line(&mut self) {
+        self.results.iter_mut().for_each(|r| *r = None);
+        self.decision = None;
+        self.buffer.clear();
+    }
+
+    fn value<OUT: Write>(&mut self, out: &mut OUT, writing: &WritingCtx, val: Scalar<'_>) {
+        if self.decision.is_some() {
+            return;
+        }
+        for (result, (path, comparison, literal)) in self.results.iter_mut().zip(&self.comparisons)
+        {
+            if result.is_none() && writing.path_matches(path) {
+                *result = Some(literal.compare(*comparison, val));
+            }
+        }
+        self.decide(out);
+    }
+
+    fn end_line<OUT: Write>(&mut self, out: &mut OUT) {
+        if self.decision.is_none() {
+            self.results.iter_mut().for_each(|r| {
+                r.get_or_insert(false);
+            });
+            self.decide(out);
+        }
+    }
+
+    fn decide<OUT: Write>(&mut self, out: &mut OUT) {
+        self.decision = self.predicate.evaluate(&self.results);
+        match self.decision {
+            Some(true) => {
+                out.write_all(&self.buffer).expect("Unable to write");
+                self.buffer.clear();
+            }
+            Some(false) => self.buffer.clear(),
+            None => (),
+        }
+    }
+}
+
+/// Where a line is written while it is being filtered.
+enum Sink<'a, OUT> {
+    Out(&'a mut OUT),
+    Buffer(&'a mut Vec<u8>),
+    Discard,
+}
+
+impl<'a, OUT: Write> Sink<'a, OUT> {
+    fn new(out: &'a mut OUT, filter: &'a mut Option<Filter>) -> Self {
+        match filter {
+            None
+            | Some(Filter {
+                decision: Some(true),
+                ..
+            }) => Sink::Out(out),
+            Some(Filter {
+                decision: None,
+                buffer,
+                ..
+            }) => Sink::Buffer(buffer),
+            Some(Filter {
+                decision: Some(false),
+                ..
+            }) => Sink::Discard,
         }
     }
+}
 
-    fn is_at_correct_location(&self, ctx: &Context) -> bool {
-        ctx.num_open_brackets() == self.num_open_brackets
-            && ctx.num_open_braces() == self.num_open_braces
+impl<OUT: Write> Write for Sink<'_, OUT> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Sink::Out(out) => out.write(buf),
+            Sink::Buffer(buffer) => buffer.write(buf),
+            Sink::Discard => Ok(buf.len()),
+        }
     }
 
-    fn is_inside_array(&self) -> bool {
-        self.inside_array
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::Out(out) => out.flush(),
+            Sink::Buffer(_) | Sink::Discard => Ok(()),
+        }
     }
+}
 
-    fn set_inside_array(&mut self) {
-        self.inside_array = true;
+/// Writes lines to the writer, or holds each one until it is finished, so
+/// that a line with an error can be dropped.
+struct Lines<'a, OUT> {
+    out: &'a mut OUT,
+    line: Option<&'a mut Vec<u8>>,
+}
+
+impl<'a, OUT: Write> Lines<'a, OUT> {
+    fn new(out: &'a mut OUT, line: &'a mut Option<Vec<u8>>) -> Self {
+        Lines {
+            out,
+            line: line.as_mut(),
+        }
     }
+}
 
-    fn is_first_value(&self) -> bool {
-        self.is_inside_array() && self.first
+impl<OUT: Write> Write for Lines<'_, OUT> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.line {
+            Some(line) => line.write(buf),
+            None => self.out.write(buf),
+        }
     }
 
-    fn first(&mut self) {
-        self.first = false;
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
     }
 }
 
+/// Finds the value at a path of selectors.
 #[derive(Debug)]
-struct Select {
+pub(crate) struct Select {
     stack: Vec<Box<dyn IsLocation + Send + Sync>>,
     selectors: Vec<Selector>,
     i: usize,
+    /// The number of selectors in the path.
+    len: usize,
 }
 
 impl Select {
-    fn is_identifier_selector_selection(&self, ctx: &Context, val: &str) -> bool {
+    /// None if there are no selectors, so the whole document is selected.
+    pub(crate) fn new(selectors: Vec<Selector>) -> Option<Self> {
+        let is_locations: Vec<Box<dyn IsLocation + Send + Sync>> = (0..selectors.len())
+            .fold(
+                vec![],
+                |mut sel: Vec<Box<dyn IsLocation + Send + Sync + 'static>>, i| match selectors[i] {
+                    Selector::Identifier(_) => {
+                        let num_open_braces = (0..i).fold(0, |mut acc, j| {
+                            if let Selector::Identifier(_) = selectors[j] {
+                                acc += 1;
+                            }
+                            acc
+                        });
+                        sel.push(Box::new(ObjSelector { num_open_braces }));
+                        sel
+                    }
+                    Selector::Index(_) => {
+                        let num_open_brackets = (0..i).fold(0, |mut acc, j| {
+                            if let Selector::Index(_) = selectors[j] {
+                                acc += 1;
+                            }
+                            acc
+                        });
+                        sel.push(Box::new(ArraySelector { num_open_brackets }));
+                        sel
+                    }
+                },
+            )
+            .into_iter()
+            .rev()
+            .collect();
+
+        if is_locations.is_empty() {
+            None
+        } else {
+            Some(Select {
+                len: selectors.len(),
+                stack: is_locations,
+                selectors: selectors.into_iter().rev().collect(),
+                i: 0,
+            })
+        }
+    }
+
+    pub(crate) fn is_identifier_selector_selection(&self, ctx: &Context, val: &str) -> bool {
         match self.selectors.last() {
-            Some(Selector::Identifier(ident)) => {
+            Some(selector @ Selector::Identifier(_)) => {
                 self.stack
                     .last()
-                    .map_or(false, |s| (**s).is_correct_location(ctx))
-                    && ident == val
+                    .is_some_and(|s| (**s).is_correct_location(ctx))
+                    && selector.matches_key(val)
             }
             _ => false,
         }
     }
 
-    fn is_array_index_selector_selection(&self, ctx: &Context) -> bool {
+    pub(crate) fn is_array_index_selector_selection(&self, ctx: &Context) -> bool {
         match self.selectors.last() {
             Some(Selector::Index(index)) => {
                 self.stack
                     .last()
-                    .map_or(false, |s| (**s).is_correct_location(ctx))
+                    .is_some_and(|s| (**s).is_correct_location(ctx))
                     && self.i == *index
             }
             _ => false,
         }
     }
 
-    fn remove_last(&mut self) -> bool {
+    pub(crate) fn remove_last(&mut self) -> bool {
         self.selectors.pop();
         self.stack.pop();
         self.i = 0;
         self.stack.is_empty() && self.selectors.is_empty()
     }
 
-    fn new_array_location(&mut self, ctx: &Context) {
+    /// At least one selector has matched, so the selection is inside of the
+    /// current top-level value.
+    pub(crate) fn is_started(&self) -> bool {
+        self.selectors.len() < self.len
+    }
+
+    pub(crate) fn new_array_location(&mut self, ctx: &Context) {
         if self
             .stack
             .last()
-            .map_or(false, |s| (**s).is_correct_location(ctx))
+            .is_some_and(|s| (**s).is_correct_location(ctx))
             && ctx.parser_status() == ParserStatus::ArrayNeedVal
         {
             self.i += 1;
@@ -152,239 +591,330 @@ where
 {
     /// Constructor.
     pub fn new(out: OUT, selectors: Vec<Selector>) -> Self {
-        let is_locations: Vec<Box<dyn IsLocation + Send + Sync>> = (0..selectors.len())
-            .fold(
-                vec![],
-                |mut sel: Vec<Box<dyn IsLocation + Send + Sync + 'static>>, i| match selectors[i] {
-                    Selector::Identifier(_) => {
-                        let num_open_braces = (0..i).fold(0, |mut acc, j| {
-                            if let Selector::Identifier(_) = selectors[j] {
-                                acc += 1;
-                            }
-                            acc
-                        });
-                        sel.push(Box::new(ObjSelector { num_open_braces }));
-                        sel
-                    }
-                    Selector::Index(_) => {
-                        let num_open_brackets = (0..i).fold(0, |mut acc, j| {
-                            if let Selector::Index(_) = selectors[j] {
-                                acc += 1;
-                            }
-                            acc
-                        });
-                        sel.push(Box::new(ArraySelector { num_open_brackets }));
-                        sel
-                    }
-                },
-            )
-            .into_iter()
-            .rev()
-            .collect();
+        Self::with_routes(vec![Route::new(out, selectors)])
+    }
+
+    /// Constructor for writing several arrays in one pass. Each selector path
+    /// writes its array values to its own writer. Parsing is aborted once every
+    /// selected array has been written.
+    pub fn with_routes<I, R>(routes: I) -> Self
+    where
+        I: IntoIterator<Item = R>,
+        R: Into<Route<OUT>>,
+    {
+        NdJsonHandler {
+            routes: routes.into_iter().map(Into::into).collect(),
+        }
+    }
 
-        let context = if is_locations.is_empty() {
-            NdjsonContext::Writing(WritingCtx::first_context())
+    fn dispatch<F>(&mut self, mut f: F) -> Status
+    where
+        F: FnMut(&mut Route<OUT>),
+    {
+        let mut done = true;
+        for route in &mut self.routes {
+            if !matches!(route.context, NdjsonContext::Done) {
+                f(route);
+            }
+            done &= matches!(route.context, NdjsonContext::Done);
+        }
+        if done {
+            Status::Abort
         } else {
-            NdjsonContext::Selecting(Select {
-                stack: is_locations,
-                selectors: selectors.into_iter().rev().collect(),
-                i: 0,
-            })
-        };
+            Status::Continue
+        }
+    }
+}
 
-        NdJsonHandler { out, context }
+impl<OUT> From<(Vec<Selector>, OUT)> for Route<OUT>
+where
+    OUT: Write,
+{
+    fn from((selectors, out): (Vec<Selector>, OUT)) -> Self {
+        Route::new(out, selectors)
     }
+}
 
-    fn increment_selectors(&mut self, ctx: &Context) {
-        let update = match &mut self.context {
-            NdjsonContext::Selecting(ref mut selecting) => selecting.remove_last(),
-            NdjsonContext::Writing(_) => false,
+impl<OUT> Route<OUT>
+where
+    OUT: Write,
+{
+    /// Constructor. The selected array has each of its values written
+    /// on its own line.
+    pub fn new(out: OUT, selectors: Vec<Selector>) -> Self {
+        let context = match Select::new(selectors) {
+            Some(select) => NdjsonContext::Selecting(select),
+            None => NdjsonContext::Writing(WritingCtx::new()),
         };
+        let whole = matches!(context, NdjsonContext::Writing(_));
+
+        Route {
+            out,
+            entries: None,
+            projection: None,
+            filter: None,
+            rejects: None,
+            line: None,
+            whole,
+            context,
+        }
+    }
 
-        if update {
-            self.context = NdjsonContext::Writing(WritingCtx::new(
-                ctx.num_open_braces(),
-                ctx.num_open_brackets() + 1,
-            ));
+    /// If the selected value is an object, write each of its members on
+    /// its own line instead of writing the object as a single line.
+    #[must_use]
+    pub fn entries(mut self, entries: ObjectEntries) -> Self {
+        self.entries = Some(entries);
+        self
+    }
+
+    /// Only write the parts of each line that the projection selects. The
+    /// filter still sees the values that are left out.
+    #[must_use]
+    pub fn project(mut self, projection: Projection) -> Self {
+        self.projection = Some(projection);
+        self
+    }
+
+    /// Only write the lines where the predicate is true. A line is held in
+    /// memory only until the predicate can be evaluated.
+    #[must_use]
+    pub fn filter(mut self, predicate: Predicate) -> Self {
+        self.filter = Some(Filter::new(predicate));
+        self
+    }
+
+    /// Recover from errors in the elements of a top-level array, with
+    /// `ParserOptions::recover_elements`: the line of an element with an
+    /// error is dropped, and a line is written to `rejects` instead, as
+    /// `{"index":<index>,"offset":<offset>,"error":<message>}`. Lines are held
+    /// in memory until they are finished. If the selected value was inside
+    /// of the element, nothing more is written.
+    ///
+    /// Without it, an error in an element aborts the parse.
+    #[must_use]
+    pub fn rejects(mut self, rejects: OUT) -> Self {
+        self.rejects = Some(rejects);
+        self.line = Some(vec![]);
+        self
+    }
+
+    fn start_line(&mut self) {
+        if let (NdjsonContext::Writing(writing), Some(filter)) = (&self.context, &mut self.filter) {
+            if !writing.in_line() {
+                filter.start_line();
+            }
         }
     }
 
-    fn map_key(&mut self, ctx: &Context, val: &str) {
-        match &mut self.context {
-            NdjsonContext::Selecting(ref mut select) => {
-                if select.is_identifier_selector_selection(ctx, val) {
-                    self.increment_selectors(ctx);
+    fn end_line(&mut self) {
+        if let NdjsonContext::Writing(writing) = &self.context {
+            if !writing.in_line() {
+                if let Some(filter) = &mut self.filter {
+                    filter.end_line(&mut Lines::new(&mut self.out, &mut self.line));
                 }
-            }
-            NdjsonContext::Writing(writing) => {
-                if !writing.is_at_correct_location(ctx)
-                    && ctx.parser_status() == ParserStatus::MapNeedKey
-                {
-                    self.out.write_all(b",").expect("Unable to write");
-                } else if writing.is_inside_array() && writing.is_at_correct_location(ctx) {
-                    if writing.is_first_value() {
-                        writing.first()
-                    } else {
-                        self.out.write_all(b"\n").expect("Unable to write");
-                    }
+                if let Some(line) = &mut self.line {
+                    self.out.write_all(line).expect("Unable to write");
+                    line.clear();
                 }
-                write!(self.out, "{}:", val).expect("Unable to write to stdout")
             }
         }
     }
 
-    fn handle_value<T: std::fmt::Display>(&mut self, ctx: &Context, val: T) -> Status {
-        match &mut self.context {
-            NdjsonContext::Selecting(ref mut select) => {
-                select.new_array_location(ctx);
+    /// Drop the line with the error, and write the error to `rejects`.
+    /// Returns false if there is no `rejects`. If the selected value was
+    /// in the element, nothing else is written.
+    fn element_error(&mut self, error: &ElementError) -> bool {
+        let rejects = match (&mut self.context, &mut self.rejects) {
+            (NdjsonContext::Writing(_), None) => return false,
+            (NdjsonContext::Writing(writing), Some(rejects)) => {
+                writing.stack.clear();
+                writing.after_key = false;
+                writing.skip_value = false;
+                rejects
             }
-            NdjsonContext::Writing(writing) => {
-                if !writing.is_at_correct_location(ctx)
-                    && ctx.parser_status() == ParserStatus::ArrayNeedVal
-                {
-                    self.out.write_all(b",").expect("Unable to write");
-                } else if writing.is_inside_array() && writing.is_at_correct_location(ctx) {
-                    if writing.is_first_value() {
-                        writing.first()
-                    } else {
-                        self.out.write_all(b"\n").expect("Unable to write");
-                    }
-                }
+            (NdjsonContext::Selecting(select), _) if select.is_started() => {
+                self.context = NdjsonContext::Done;
+                return true;
+            }
+            _ => return true,
+        };
+        if let Some(line) = &mut self.line {
+            line.clear();
+        }
+        if let Some(filter) = &mut self.filter {
+            filter.start_line();
+        }
+        writeln!(
+            rejects,
+            "{{\"index\":{},\"offset\":{},\"error\":{}}}",
+            error.index,
+            error.offset,
+            Value::String(error.error.to_string())
+        )
+        .expect("Unable to write");
+        if !self.whole {
+            self.context = NdjsonContext::Done;
+        }
+        true
+    }
+
+    fn increment_selectors(&mut self) {
+        let update = match &mut self.context {
+            NdjsonContext::Selecting(ref mut selecting) => selecting.remove_last(),
+            NdjsonContext::Writing(_) | NdjsonContext::Done => false,
+        };
+
+        if update {
+            self.context = NdjsonContext::Writing(WritingCtx::new());
+        }
+    }
 
-                write!(self.out, "{}", val).expect("Unable to write to stdout");
+    /// An array value is starting, which may be the value an index selector selects.
+    fn select_array_value(&mut self, ctx: &Context) {
+        if let NdjsonContext::Selecting(ref mut select) = &mut self.context {
+            select.new_array_location(ctx);
+            if select.is_array_index_selector_selection(ctx) {
+                self.increment_selectors();
             }
         }
-        Status::Continue
     }
 
-    fn map_start(&mut self, ctx: &Context) {
+    fn map_key(&mut self, ctx: &Context, val: &str) {
+        self.start_line();
+
         match &mut self.context {
             NdjsonContext::Selecting(ref mut select) => {
-                select.new_array_location(ctx);
-                if select.is_array_index_selector_selection(ctx) {
-                    self.increment_selectors(ctx);
+                if select.is_identifier_selector_selection(ctx, val) {
+                    self.increment_selectors();
                 }
             }
             NdjsonContext::Writing(writing) => {
-                if !writing.is_at_correct_location(ctx)
-                    && ctx.parser_status() == ParserStatus::ArrayNeedVal
-                {
-                    self.out.write_all(b",").expect("Unable to write");
-                } else if writing.is_at_correct_location(ctx) {
-                    if writing.is_first_value() {
-                        writing.first()
-                    } else {
-                        self.out.write_all(b"\n").expect("Unable to write");
-                    }
-                }
-                self.out.write_all(b"{").expect("Unable to write");
+                writing.map_key(
+                    &mut Sink::new(
+                        &mut Lines::new(&mut self.out, &mut self.line),
+                        &mut self.filter,
+                    ),
+                    val,
+                    self.projection.as_ref(),
+                );
             }
+            NdjsonContext::Done => (),
         }
     }
 
-    fn map_end(&mut self, ctx: &Context) {
-        if let NdjsonContext::Writing(writing) = &self.context {
-            self.out.write_all(b"}").expect("Unable to write");
-            if writing.is_at_correct_location(ctx) {
-                self.out
-                    .write_all(b"\n")
-                    .expect("Unable to write to stdout");
+    fn handle_value(&mut self, ctx: &Context, val: Scalar<'_>) {
+        self.select_array_value(ctx);
+        self.start_line();
+
+        if let NdjsonContext::Writing(writing) = &mut self.context {
+            writing.enter_value();
+            if let Some(filter) = &mut self.filter {
+                filter.value(&mut Lines::new(&mut self.out, &mut self.line), writing, val);
+            }
+            if writing.value(
+                &mut Sink::new(
+                    &mut Lines::new(&mut self.out, &mut self.line),
+                    &mut self.filter,
+                ),
+                val,
+                self.projection.as_ref(),
+            ) {
+                self.end_line();
+                self.context = NdjsonContext::Done;
             }
         }
+        self.end_line();
     }
 
-    fn array_start(&mut self, ctx: &Context) {
-        if let NdjsonContext::Selecting(ref mut select) = &mut self.context {
-            select.new_array_location(ctx);
-            if select.is_array_index_selector_selection(ctx) {
-                self.increment_selectors(ctx);
-            }
-        }
+    fn start(&mut self, ctx: &Context, enclosing: Enclosing) {
+        self.select_array_value(ctx);
+        self.start_line();
 
         if let NdjsonContext::Writing(writing) = &mut self.context {
-            if writing.is_inside_array() {
-                if !writing.is_at_correct_location(ctx)
-                    && ctx.parser_status() == ParserStatus::ArrayNeedVal
-                {
-                    self.out.write_all(b",").expect("Unable to write");
-                } else if writing.is_at_correct_location(ctx) {
-                    if writing.is_first_value() {
-                        writing.first()
-                    } else {
-                        self.out.write_all(b"\n").expect("Unable to write");
-                    }
-                }
-                self.out.write_all(b"[").expect("Unable to write");
-            } else {
-                writing.set_inside_array();
-            }
+            writing.enter_value();
+            writing.start(
+                &mut Sink::new(
+                    &mut Lines::new(&mut self.out, &mut self.line),
+                    &mut self.filter,
+                ),
+                enclosing,
+                self.entries,
+                self.projection.as_ref(),
+            );
         }
+        self.end_line();
     }
 
-    fn array_end(&mut self, ctx: &Context) -> Status {
-        if let NdjsonContext::Writing(writing) = &self.context {
-            if writing.is_inside_array() && writing.is_at_correct_location(ctx) {
-                self.out
-                    .write_all(b"\n")
-                    .expect("Unable to write to stdout");
-            }
-
-            if writing.is_at_correct_location(ctx) {
-                return Status::Abort;
+    fn end(&mut self, enclosing: Enclosing) {
+        if let NdjsonContext::Writing(writing) = &mut self.context {
+            if writing.end(
+                &mut Sink::new(
+                    &mut Lines::new(&mut self.out, &mut self.line),
+                    &mut self.filter,
+                ),
+                enclosing,
+            ) {
+                self.end_line();
+                self.context = NdjsonContext::Done;
+                return;
             }
-
-            self.out.write_all(b"]").expect("Unable to write");
-            Status::Continue
-        } else {
-            Status::Continue
         }
+        self.end_line();
     }
 }
 
 impl<OUT: Write> Handler for NdJsonHandler<OUT> {
     fn handle_null(&mut self, ctx: &Context) -> Status {
-        self.handle_value(ctx, "null")
+        self.dispatch(|route| route.handle_value(ctx, Scalar::Null))
     }
 
     fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
-        self.handle_value(ctx, val)
+        self.dispatch(|route| route.handle_value(ctx, Scalar::Double(val)))
     }
 
     fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
-        self.handle_value(ctx, val)
+        self.dispatch(|route| route.handle_value(ctx, Scalar::Int(val)))
     }
 
     fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
-        self.handle_value(ctx, val)
+        self.dispatch(|route| route.handle_value(ctx, Scalar::Bool(val)))
     }
 
     fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
-        self.handle_value(ctx, val)
+        self.dispatch(|route| route.handle_value(ctx, Scalar::String(val)))
     }
 
     fn handle_start_map(&mut self, ctx: &Context) -> Status {
-        self.map_start(ctx);
-        Status::Continue
+        self.dispatch(|route| route.start(ctx, Enclosing::LeftBrace))
     }
 
-    fn handle_end_map(&mut self, ctx: &Context) -> Status {
-        self.map_end(ctx);
-        Status::Continue
+    fn handle_end_map(&mut self, _ctx: &Context) -> Status {
+        self.dispatch(|route| route.end(Enclosing::LeftBrace))
     }
 
     fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
-        self.map_key(ctx, key);
-        Status::Continue
+        self.dispatch(|route| route.map_key(ctx, key))
     }
 
     fn handle_start_array(&mut self, ctx: &Context) -> Status {
-        self.array_start(ctx);
+        self.dispatch(|route| route.start(ctx, Enclosing::LeftBracket))
+    }
 
-        Status::Continue
+    fn handle_end_array(&mut self, _ctx: &Context) -> Status {
+        self.dispatch(|route| route.end(Enclosing::LeftBracket))
     }
 
-    fn handle_end_array(&mut self, ctx: &Context) -> Status {
-        self.array_end(ctx)
+    fn handle_element_error(&mut self, _ctx: &Context, error: &ElementError) -> Status {
+        let mut rejected = true;
+        for route in &mut self.routes {
+            rejected &= route.element_error(error);
+        }
+        if rejected {
+            Status::Continue
+        } else {
+            Status::Abort
+        }
     }
 }
 
@@ -397,9 +927,24 @@ pub enum Selector {
     Index(usize),
 }
 
+impl Selector {
+    /// The key, as passed to a `Handler` with its quotes, is selected. The
+    /// identifier can be written with or without the quotes.
+    pub(crate) fn matches_key(&self, key: &str) -> bool {
+        match self {
+            Selector::Identifier(ident) => {
+                ident == key
+                    || key.strip_prefix('"').and_then(|k| k.strip_suffix('"'))
+                        == Some(ident.as_str())
+            }
+            Selector::Index(_) => false,
+        }
+    }
+}
+
 use lalrpop_util::lalrpop_mod;
 
-lalrpop_mod!(pub selector_parser);
+lalrpop_mod!(#[allow(clippy::all)] pub selector_parser);
 
 pub use self::selector_parser::SelectorParser;
 
@@ -409,7 +954,10 @@ mod tests {
         selector_parser::{ArrayTokenParser, IdentifierTokenParser, SelectorParser},
         *,
     };
-    use crate::Parser;
+    use crate::{
+        predicate::{PathParser, PredicateParser},
+        Parser, ParserOptions,
+    };
     use pretty_assertions::assert_eq;
     use std::io::BufReader;
 
@@ -541,6 +1089,303 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_multiple_routes() {
+        let mut input = BufReader::new(
+            "{ \"nodes\": [{ \"id\": 1 }, { \"id\": 2 }], \"edges\": [[1, 2]], \"rest\": [true] }"
+                .as_bytes(),
+        );
+
+        let mut nodes = vec![];
+        let mut edges = vec![];
+
+        let mut handler = NdJsonHandler::with_routes(vec![
+            (
+                vec![Selector::Identifier("\"nodes\"".to_owned())],
+                &mut nodes,
+            ),
+            (
+                vec![Selector::Identifier("\"edges\"".to_owned())],
+                &mut edges,
+            ),
+        ]);
+
+        let mut parser = Parser::new(&mut handler);
+
+        parser.parse::<BufReader<&[u8]>>(&mut input).unwrap();
+
+        assert_eq!(nodes, "{\"id\":1}\n{\"id\":2}\n".as_bytes());
+        assert_eq!(edges, "[1,2]\n".as_bytes());
+    }
+
+    fn assert_entries(
+        input: &[u8],
+        selectors: Vec<Selector>,
+        entries: ObjectEntries,
+        output: &[u8],
+    ) {
+        let mut input = BufReader::new(input);
+
+        let mut out = vec![];
+
+        let mut handler =
+            NdJsonHandler::with_routes(vec![Route::new(&mut out, selectors).entries(entries)]);
+
+        let mut parser = Parser::new(&mut handler);
+
+        parser.parse::<BufReader<&[u8]>>(&mut input).unwrap();
+
+        assert_eq!(out, output);
+    }
+
+    #[test]
+    fn test_object_entries_key_value() {
+        assert_entries(
+            "{ \"users\": { \"a1\": { \"name\": \"Ada\" }, \"b2\": [1, { \"x\": null }], \"c3\": 7 } }"
+                .as_bytes(),
+            vec![Selector::Identifier("\"users\"".to_owned())],
+            ObjectEntries::KeyValue,
+            "{\"key\":\"a1\",\"value\":{\"name\":\"Ada\"}}\n{\"key\":\"b2\",\"value\":[1,{\"x\":null}]}\n{\"key\":\"c3\",\"value\":7}\n"
+                .as_bytes(),
+        );
+    }
+
+    #[test]
+    fn test_object_entries_pair() {
+        assert_entries(
+            "{ \"a\": true, \"b\": { \"c\": [] } }".as_bytes(),
+            vec![],
+            ObjectEntries::Pair,
+            "[\"a\",true]\n[\"b\",{\"c\":[]}]\n".as_bytes(),
+        );
+    }
+
+    #[test]
+    fn test_object_entries_of_array() {
+        assert_entries(
+            "{ \"foo\": [{ \"bar\": 1 }, 2] }".as_bytes(),
+            vec![Selector::Identifier("\"foo\"".to_owned())],
+            ObjectEntries::Pair,
+            "{\"bar\":1}\n2\n".as_bytes(),
+        );
+    }
+
+    #[test]
+    fn test_selected_object_without_entries() {
+        assert_ndjson(
+            "{ \"foo\": { \"bar\": [1, 2] }, \"baz\": 3 }".as_bytes(),
+            vec![Selector::Identifier("\"foo\"".to_owned())],
+            "{\"bar\":[1,2]}\n".as_bytes(),
+        );
+    }
+
+    fn assert_filtered(input: &[u8], selectors: &str, predicate: &str, output: &[u8]) {
+        let mut input = BufReader::new(input);
+
+        let mut out = vec![];
+
+        let route = Route::new(&mut out, SelectorParser::new().parse(selectors).unwrap())
+            .filter(PredicateParser::new().parse(predicate).unwrap());
+        let mut handler = NdJsonHandler::with_routes(vec![route]);
+
+        let mut parser = Parser::new(&mut handler);
+
+        parser.parse::<BufReader<&[u8]>>(&mut input).unwrap();
+
+        assert_eq!(out, output);
+    }
+
+    #[test]
+    fn test_filter_by_nested_key() {
+        assert_filtered(
+            "{ \"features\": [{ \"properties\": { \"type\": \"Feature\" }, \"id\": 1 }, { \"properties\": { \"type\": \"Other\" }, \"id\": 2 }, { \"id\": 3, \"properties\": { \"type\": \"Feature\" } }] }"
+                .as_bytes(),
+            "d.features",
+            ".properties.type == \"Feature\"",
+            "{\"properties\":{\"type\":\"Feature\"},\"id\":1}\n{\"id\":3,\"properties\":{\"type\":\"Feature\"}}\n"
+                .as_bytes(),
+        );
+    }
+
+    #[test]
+    fn test_filter_missing_and_scalars() {
+        assert_filtered(
+            "[{ \"year\": 1999 }, { \"name\": \"x\" }, { \"year\": 2001.5, \"tags\": [1, 2] }, 2005]"
+                .as_bytes(),
+            "d",
+            ".year > 2000 || . >= 2005",
+            "{\"year\":2001.5,\"tags\":[1,2]}\n2005\n".as_bytes(),
+        );
+
+        assert_filtered(
+            "{ \"rows\": [[1, 5], [2, 6], [3]] }".as_bytes(),
+            "d.rows",
+            "!([1] == 6)",
+            "[1,5]\n[3]\n".as_bytes(),
+        );
+    }
+
+    #[test]
+    fn test_filter_object_entries() {
+        let mut out = vec![];
+
+        let route = Route::new(&mut out, vec![])
+            .entries(ObjectEntries::Pair)
+            .filter(PredicateParser::new().parse(".ok == true").unwrap());
+        let mut handler = NdJsonHandler::with_routes(vec![route]);
+
+        let mut parser = Parser::new(&mut handler);
+
+        parser
+            .parse(&mut "{ \"a\": { \"ok\": true }, \"b\": { \"ok\": false } }".as_bytes())
+            .unwrap();
+
+        assert_eq!(out, "[\"a\",{\"ok\":true}]\n".as_bytes());
+    }
+
+    #[test]
+    fn test_rejects() {
+        let input = r#"[{ "a": 1 }, { "a": # }, { "a": tru, "b": [1] }, 5, { "a": "x" }]"#;
+        let options = ParserOptions::new().recover_elements(true);
+
+        let mut out = vec![];
+        let mut rejects = vec![];
+        let route = Route::new(&mut out, vec![]).rejects(&mut rejects);
+        let mut handler = NdJsonHandler::with_routes(vec![route]);
+        Parser::new(&mut handler)
+            .options(options)
+            .parse(&mut input.as_bytes())
+            .unwrap();
+        drop(handler);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"a\":1}\n5\n{\"a\":\"x\"}\n"
+        );
+        assert_eq!(
+            String::from_utf8(rejects).unwrap(),
+            "{\"index\":1,\"offset\":20,\"error\":\"Error: Malformed Json: Unexpected byte '#'\"}\n\
+             {\"index\":2,\"offset\":35,\"error\":\"Error: Malformed Json: Invalid literal tru\"}\n"
+        );
+
+        // Without rejects the error aborts the parse.
+        let mut out = vec![];
+        let mut handler = NdJsonHandler::new(&mut out, vec![]);
+        assert!(Parser::new(&mut handler)
+            .options(options)
+            .parse(&mut input.as_bytes())
+            .is_err());
+    }
+
+    #[test]
+    fn test_rejects_nested_selection() {
+        let options = ParserOptions::new().recover_elements(true);
+        let rejected = |input: &str, selectors: Vec<Selector>| {
+            let mut out = vec![];
+            let mut rejects = vec![];
+            let route = Route::new(&mut out, selectors).rejects(&mut rejects);
+            let mut handler = NdJsonHandler::with_routes(vec![route]);
+            Parser::new(&mut handler)
+                .options(options)
+                .parse(&mut input.as_bytes())
+                .unwrap();
+            drop(handler);
+            (String::from_utf8(out).unwrap(), rejects.len())
+        };
+
+        // The selected array was in the element with the error.
+        let (out, rejects) = rejected("[[1,2],[3, x],[4,5],[6]]", vec![Selector::Index(1)]);
+        assert_eq!(out, "3\n");
+        assert!(rejects > 0);
+
+        // The error came before the selected value was found.
+        let (out, rejects) = rejected(
+            "[[0],[[9],[x]],[[4]]]",
+            vec![Selector::Index(1), Selector::Index(1), Selector::Index(0)],
+        );
+        assert_eq!(out, "");
+        assert_eq!(rejects, 0);
+    }
+
+    fn assert_projected(
+        input: &[u8],
+        route: fn(&mut Vec<u8>) -> Route<&mut Vec<u8>>,
+        output: &[u8],
+    ) {
+        let mut input = BufReader::new(input);
+
+        let mut out = vec![];
+
+        let mut handler = NdJsonHandler::with_routes(vec![route(&mut out)]);
+
+        let mut parser = Parser::new(&mut handler);
+
+        parser.parse::<BufReader<&[u8]>>(&mut input).unwrap();
+
+        assert_eq!(out, output);
+    }
+
+    const FEATURES: &str = "{ \"features\": [{ \"id\": 1, \"geometry\": { \"coordinates\": [[1.5, 2.5]] }, \"properties\": { \"name\": \"a\", \"type\": \"Feature\" } }, { \"properties\": { \"type\": \"Other\" }, \"id\": 2, \"geometry\": null }] }";
+
+    #[test]
+    fn test_projection_include() {
+        assert_projected(
+            FEATURES.as_bytes(),
+            |out| {
+                Route::new(out, SelectorParser::new().parse("d.features").unwrap()).project(
+                    Projection::Include(vec![
+                        PathParser::new().parse(".properties.name").unwrap(),
+                        PathParser::new().parse(".id").unwrap(),
+                    ]),
+                )
+            },
+            "{\"id\":1,\"properties\":{\"name\":\"a\"}}\n{\"properties\":{},\"id\":2}\n".as_bytes(),
+        );
+    }
+
+    #[test]
+    fn test_projection_exclude() {
+        assert_projected(
+            FEATURES.as_bytes(),
+            |out| {
+                Route::new(out, SelectorParser::new().parse("d.features").unwrap()).project(
+                    Projection::Exclude(vec![
+                        PathParser::new().parse(".geometry").unwrap(),
+                        PathParser::new().parse(".properties.type").unwrap(),
+                    ]),
+                )
+            },
+            "{\"id\":1,\"properties\":{\"name\":\"a\"}}\n{\"properties\":{},\"id\":2}\n".as_bytes(),
+        );
+
+        assert_projected(
+            "[[1, 2, 3], [4, 5]]".as_bytes(),
+            |out| {
+                Route::new(out, vec![]).project(Projection::Exclude(vec![vec![Selector::Index(1)]]))
+            },
+            "[1,3]\n[4]\n".as_bytes(),
+        );
+    }
+
+    #[test]
+    fn test_projection_with_filter() {
+        assert_projected(
+            FEATURES.as_bytes(),
+            |out| {
+                Route::new(out, SelectorParser::new().parse("d.features").unwrap())
+                    .project(Projection::Include(vec![PathParser::new()
+                        .parse(".id")
+                        .unwrap()]))
+                    .filter(
+                        PredicateParser::new()
+                            .parse(".properties.type == \"Other\"")
+                            .unwrap(),
+                    )
+            },
+            "{\"id\":2}\n".as_bytes(),
+        );
+    }
+
     #[test]
     fn test_selector_parser() {
         assert_eq!(
@@ -18,245 +18,309 @@
 //! Parser struct should be used to parse bytes in to json.
 
 use crate::{
-    common::{Enclosing, ParserStatus},
-    Context, Handler, Status,
+    common::ParserStatus,
+    encoding::{DecoderState, InvalidInput},
+    events::{Counts, Events},
+    lexer::Token,
+    Context, Encoding, Handler, KeyCache,
 };
-use json_tools::{Buffer, BufferType, Lexer, Token, TokenType};
 use std::io::BufRead;
 
-/// Main Parser struct.
-pub struct Parser<'a, H> {
-    handler: &'a mut H,
+/// Limits on the resources used by a parse, so that hostile input can't
+/// exhaust memory, and relaxations of the JSON syntax. Each limit that is
+/// exceeded has its own `ParseError`. By default nothing is limited and the
+/// syntax is strict.
+///
+/// The limits on bytes and elements are for the whole input, across calls to
+/// `Parser::parse`.
+///
+/// Strings in single quotes and unquoted keys are passed to a `Handler` as
+/// if they were in double quotes.
+///
+/// ```
+///     use yajlish::{ParseError, ParserOptions, Events};
+///
+///     let options = ParserOptions::new().max_depth(2);
+///     let mut events = Events::new("[[[1]]]".as_bytes()).options(options);
+///     assert_eq!(events.nth(2), Some(Err(ParseError::DepthLimitExceeded(2))));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ParserOptions {
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) max_string_length: Option<usize>,
+    pub(crate) max_number_length: Option<usize>,
+    pub(crate) max_bytes: Option<u64>,
+    pub(crate) max_elements: Option<u64>,
+    pub(crate) allow_comments: bool,
+    pub(crate) allow_trailing_commas: bool,
+    pub(crate) allow_single_quotes: bool,
+    pub(crate) allow_unquoted_keys: bool,
+    pub(crate) allow_nan_infinity: bool,
+    pub(crate) json5: bool,
+    pub(crate) allow_partial_values: bool,
+    pub(crate) recover_elements: bool,
+    pub(crate) replace_invalid_utf8: bool,
+}
+
+impl ParserOptions {
+    /// Constructor, with no limits.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// At the end of the JSON, close the objects and arrays that are open,
+    /// passing their ends to the `Handler`, instead of returning an error.
+    /// A string or literal that was cut off is dropped, as is a number in an
+    /// object or array that ends at the end of the JSON, and a key whose
+    /// value was dropped or is missing. `Parser::truncated` tells if this
+    /// happened.
+    ///
+    /// The end of each read passed to `Parser::parse` is the end of the JSON.
+    #[must_use]
+    pub fn allow_partial_values(mut self, allow: bool) -> Self {
+        self.allow_partial_values = allow;
+        self
+    }
+
+    /// Replace invalid UTF-8 in strings and keys, and unpaired surrogates in
+    /// UTF-16, with U+FFFD instead of returning `ParseError::Utf8Error`.
+    #[must_use]
+    pub fn replace_invalid_utf8(mut self, replace: bool) -> Self {
+        self.replace_invalid_utf8 = replace;
+        self
+    }
+
+    /// When the JSON is an array, recover from an error in one of its
+    /// elements by skipping to the next element, and passing the error to
+    /// `Handler::handle_element_error`. Errors from reading, limits on the
+    /// whole document and the end of the JSON can't be recovered from.
+    #[must_use]
+    pub fn recover_elements(mut self, recover: bool) -> Self {
+        self.recover_elements = recover;
+        self
+    }
+
+    /// Constructor for [JSON5](https://spec.json5.org), with no limits. This
+    /// allows everything the other options do, as well as hex numbers,
+    /// numbers with a `+` sign or a leading or trailing decimal point, the
+    /// escapes of JSON5, line continuations in strings, more whitespace and
+    /// keys that are ECMAScript identifiers.
+    ///
+    /// Numbers are passed to a `Handler` like other numbers, hex numbers
+    /// as integers. Strings are passed on with the escapes of JSON.
+    ///
+    /// The cases of the JSON5 test suite (json5/json5-tests) are run in
+    /// `tests/test_json5.rs`.
+    #[must_use]
+    pub fn json5() -> Self {
+        ParserOptions {
+            allow_comments: true,
+            allow_trailing_commas: true,
+            allow_single_quotes: true,
+            allow_unquoted_keys: true,
+            allow_nan_infinity: true,
+            json5: true,
+            ..Self::default()
+        }
+    }
+
+    /// The number of objects and arrays that can be open at once.
+    #[must_use]
+    pub fn max_depth(mut self, max: usize) -> Self {
+        self.max_depth = Some(max);
+        self
+    }
+
+    /// The length of a string or key in bytes, as it is in the JSON with
+    /// its escapes but without its quotes.
+    #[must_use]
+    pub fn max_string_length(mut self, max: usize) -> Self {
+        self.max_string_length = Some(max);
+        self
+    }
+
+    /// The length of a number in bytes.
+    #[must_use]
+    pub fn max_number_length(mut self, max: usize) -> Self {
+        self.max_number_length = Some(max);
+        self
+    }
+
+    /// The number of bytes read.
+    #[must_use]
+    pub fn max_bytes(mut self, max: u64) -> Self {
+        self.max_bytes = Some(max);
+        self
+    }
+
+    /// The number of values, counting objects and arrays but not keys.
+    #[must_use]
+    pub fn max_elements(mut self, max: u64) -> Self {
+        self.max_elements = Some(max);
+        self
+    }
+
+    /// Allow `//` and `/* */` comments where there can be whitespace.
+    #[must_use]
+    pub fn allow_comments(mut self, allow: bool) -> Self {
+        self.allow_comments = allow;
+        self
+    }
+
+    /// Allow a comma after the last value of an array or object.
+    #[must_use]
+    pub fn allow_trailing_commas(mut self, allow: bool) -> Self {
+        self.allow_trailing_commas = allow;
+        self
+    }
+
+    /// Allow strings and keys in single quotes, like `'it\'s'`.
+    #[must_use]
+    pub fn allow_single_quotes(mut self, allow: bool) -> Self {
+        self.allow_single_quotes = allow;
+        self
+    }
+
+    /// Allow keys without quotes, made of ASCII letters, digits, `_` and
+    /// `$` and not starting with a digit.
+    #[must_use]
+    pub fn allow_unquoted_keys(mut self, allow: bool) -> Self {
+        self.allow_unquoted_keys = allow;
+        self
+    }
+
+    /// Allow `NaN`, `Infinity` and `-Infinity`, which are passed to
+    /// `Handler::handle_double`.
+    #[must_use]
+    pub fn allow_nan_infinity(mut self, allow: bool) -> Self {
+        self.allow_nan_infinity = allow;
+        self
+    }
+}
+
+/// Main Parser struct. It owns its Handler, which is a `&mut` reference to
+/// the handler for a Parser made with `new`.
+pub struct Parser<H> {
+    handler: H,
     context: Context,
+    keys: Option<KeyCache>,
+    options: ParserOptions,
+    truncated: bool,
+    decoder: DecoderState,
+    lookahead: Vec<Token>,
+    counts: Counts,
 }
 
-impl<'a, H: Handler> Parser<'a, H> {
+impl<'a, H: Handler> Parser<&'a mut H> {
     /// Construct a new Parser from a Handler.
     pub fn new(handler: &'a mut H) -> Self {
+        Self::with_handler(handler)
+    }
+}
+
+impl<H: Handler> Parser<H> {
+    /// Construct a new Parser that owns the Handler. Get it back with
+    /// `into_inner`.
+    pub fn with_handler(handler: H) -> Self {
         Parser {
             handler,
             context: Context::default(),
+            keys: None,
+            options: ParserOptions::default(),
+            truncated: false,
+            decoder: DecoderState::default(),
+            lookahead: vec![],
+            counts: Counts::default(),
         }
     }
 
+    /// Intern object keys in `keys`, and pass them to
+    /// `Handler::handle_interned_key` with their ID.
+    #[must_use]
+    pub fn key_cache(mut self, keys: KeyCache) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    /// Limit the resources used by the parse, see `ParserOptions`.
+    #[must_use]
+    pub fn options(mut self, options: ParserOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// True if the JSON ended in a value, and it was closed because of
+    /// `ParserOptions::allow_partial_values`.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The encoding of the input, once it has been detected. Later calls to
+    /// `parse` continue in the same encoding.
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.decoder.encoding()
+    }
+
+    /// The keys that were interned, if there is a `KeyCache`.
+    pub fn keys(&self) -> Option<&KeyCache> {
+        self.keys.as_ref()
+    }
+
+    /// Consume the Parser and return its Handler.
+    pub fn into_inner(self) -> H {
+        self.handler
+    }
+
     /// Parse until Handler method returns Abort or EOF.
     ///
     /// # Errors
     ///    - Will return an error Result if the JSON is malformed, or if the underlying
-    ///   Reader returns an error.
+    ///      Reader returns an error.
     pub fn parse<B: BufRead>(&mut self, read: &mut B) -> Result<(), ParseError> {
-        let context = &mut self.context;
-
-        let mut lexer = Lexer::new(Wrapper(read), BufferType::Bytes(20));
-
-        while !matches!(
-            context.parser_status(),
-            ParserStatus::ParseComplete | ParserStatus::LexicalError
-        ) {
-            let status = match lexer.next() {
-                Some(Token {
-                    kind: TokenType::BracketClose,
-                    ..
-                }) => {
-                    let status = self.handler.handle_end_array(context);
-                    if context.last_enclosing() == Some(Enclosing::LeftBracket) {
-                        context.remove_last_enclosing();
-                        context.dec_brackets();
-                    } else {
-                        return Err(ParseError::MalformedJson(format!("Parsed right bracket without a corresponding left bracket: braces: {}, brackets: {}", context.num_open_braces(), context.num_open_brackets())));
-                    }
-
-                    if context.last_enclosing() == Some(Enclosing::LeftBrace) {
-                        context.update_status(ParserStatus::MapGotVal);
-                    } else if context.last_enclosing() == Some(Enclosing::LeftBracket) {
-                        context.update_status(ParserStatus::ArrayGotVal);
-                    } else {
-                        context.update_status(ParserStatus::GotValue);
-                    }
-                    Some(status)
-                }
-                Some(Token {
-                    kind: TokenType::CurlyClose,
-                    ..
-                }) => {
-                    let status = self.handler.handle_end_map(context);
-
-                    if context.last_enclosing() == Some(Enclosing::LeftBrace) {
-                        context.remove_last_enclosing();
-                        context.dec_braces();
-                    } else {
-                        context.update_status(ParserStatus::LexicalError);
-                    }
-
-                    if context.last_enclosing() == Some(Enclosing::LeftBrace) {
-                        context.update_status(ParserStatus::MapGotVal);
-                    } else if context.last_enclosing() == Some(Enclosing::LeftBracket) {
-                        context.update_status(ParserStatus::ArrayGotVal);
-                    } else {
-                        context.update_status(ParserStatus::GotValue);
-                    }
-
-                    Some(status)
-                }
-                Some(Token {
-                    kind: TokenType::BracketOpen,
-                    ..
-                }) => {
-                    let status = self.handler.handle_start_array(context);
-                    context.add_enclosing(Enclosing::LeftBracket);
-                    context.inc_brackets();
-                    context.update_status(ParserStatus::ArrayStart);
-                    Some(status)
-                }
-                Some(Token {
-                    kind: TokenType::CurlyOpen,
-                    ..
-                }) => {
-                    let status = self.handler.handle_start_map(context);
-                    context.add_enclosing(Enclosing::LeftBrace);
-                    context.inc_braces();
-                    context.update_status(ParserStatus::MapStart);
-
-                    Some(status)
-                }
-                Some(Token {
-                    kind: TokenType::Null,
-                    ..
-                }) => {
-                    let status = self.handler.handle_null(context);
-
-                    update_context_status_value(context);
-
-                    Some(status)
-                }
-                Some(Token {
-                    kind: TokenType::Number,
-                    buf,
-                }) => {
-                    let status = match buf {
-                        Buffer::MultiByte(b) => match std::str::from_utf8(&b) {
-                            Ok(s) => match s.parse::<i64>() {
-                                Ok(num) => self.handler.handle_int(context, num),
-                                Err(_) => match s.parse::<f64>() {
-                                    Ok(num) => self.handler.handle_double(context, num),
-                                    Err(_) => panic!("Could not parse number as i64 or f64"),
-                                },
-                            },
-                            Err(e) => return Err(ParseError::MalformedJson(e.to_string())),
-                        },
-                        Buffer::Span(_) => panic!("Unexpected Span when handling number"),
-                    };
-
-                    update_context_status_value(context);
-
-                    Some(status)
-                }
-                Some(Token {
-                    kind: TokenType::String,
-                    buf,
-                }) => {
-                    let string = match buf {
-                        Buffer::MultiByte(ref b) => match std::str::from_utf8(b) {
-                            Ok(s) => s,
-                            Err(e) => return Err(ParseError::MalformedJson(e.to_string())),
-                        },
-                        Buffer::Span(_) => panic!("Unexpected span in string buffer"),
-                    };
-
-                    if context.parser_status() == ParserStatus::ArrayNeedVal
-                        || context.parser_status() == ParserStatus::ArrayStart
-                    {
-                        let status = self.handler.handle_string(context, string);
-                        context.update_status(ParserStatus::ArrayGotVal);
-                        Some(status)
-                    } else if context.parser_status() == ParserStatus::MapNeedVal {
-                        let status = self.handler.handle_string(context, string);
-                        context.update_status(ParserStatus::MapGotVal);
-                        Some(status)
-                    } else if context.parser_status() == ParserStatus::Start {
-                        let status = self.handler.handle_string(context, string);
-                        context.update_status(ParserStatus::GotValue);
-                        Some(status)
-                    } else if context.parser_status() == ParserStatus::MapNeedKey
-                        || context.parser_status() == ParserStatus::MapStart
-                    {
-                        let status = self.handler.handle_map_key(context, string);
-                        context.update_status(ParserStatus::MapSep);
-                        Some(status)
-                    } else {
-                        context.update_status(ParserStatus::LexicalError);
-                        None
-                    }
-                }
-                Some(Token {
-                    kind: TokenType::BooleanTrue,
-                    ..
-                }) => {
-                    let status = self.handler.handle_bool(context, true);
-
-                    update_context_status_value(context);
-
-                    Some(status)
-                }
-                Some(Token {
-                    kind: TokenType::BooleanFalse,
-                    ..
-                }) => {
-                    let status = self.handler.handle_bool(context, false);
-
-                    update_context_status_value(context);
-
-                    Some(status)
-                }
-                Some(Token {
-                    kind: TokenType::Comma,
-                    ..
-                }) => {
-                    if context.parser_status() == ParserStatus::MapGotVal {
-                        context.update_status(ParserStatus::MapNeedKey);
-                    } else if context.parser_status() == ParserStatus::ArrayGotVal {
-                        context.update_status(ParserStatus::ArrayNeedVal);
-                    } else {
-                        context.update_status(ParserStatus::LexicalError);
-                    }
-
-                    None
-                }
-                Some(Token {
-                    kind: TokenType::Colon,
-                    ..
-                }) => {
-                    if context.parser_status() == ParserStatus::MapSep {
-                        context.update_status(ParserStatus::MapNeedVal);
-                    }
-
-                    None
-                }
-                Some(Token {
-                    kind: TokenType::Invalid,
-                    buf,
-                }) => {
-                    return Err(ParseError::MalformedJson(format!("{:?}", buf)));
-                }
-                None => {
-                    self.context.update_status(ParserStatus::ParseComplete);
-                    break;
-                }
-            };
-
-            if status == Some(Status::Abort) {
-                return Ok(());
-            }
-        }
-        if self.context.parser_status() == ParserStatus::LexicalError {
-            return Err(ParseError::MalformedJson(format!(
-                "Parse failed due to malformed json: open braces: {}, open brackets: {}",
-                self.context.num_open_braces(),
-                self.context.num_open_brackets()
-            )));
-        }
+        let mut events =
+            Events::with_context(read, std::mem::take(&mut self.context)).options(self.options);
+        events.set_decoder_state(std::mem::take(&mut self.decoder));
+        events.set_lookahead(std::mem::take(&mut self.lookahead));
+        events.set_counts(self.counts);
+        events.set_key_cache(self.keys.take());
+        let result = events.drive(&mut self.handler);
+        self.truncated |= events.truncated();
+        self.decoder = events.take_decoder_state();
+        self.lookahead = events.take_lookahead();
+        self.counts = events.counts();
+        self.keys = events.take_key_cache();
+        self.context = events.into_context();
+        result
+    }
 
-        Ok(())
+    /// Parse a file by memory-mapping it, so that the lexer reads the file's
+    /// pages directly instead of copying them through a `BufReader`. Like
+    /// `parse`, this doesn't call `finish_parse`.
+    ///
+    /// This isn't a zero-copy parse: the lexer still copies each string,
+    /// key and number into an owned `String`, as it does for any reader.
+    ///
+    /// The file must not be changed while it is parsed.
+    ///
+    /// Requires feature `mmap`.
+    ///
+    /// # Errors
+    ///    - Will return an error Result if the file can't be opened or
+    ///      mapped, or the JSON is malformed.
+    #[cfg(feature = "mmap")]
+    pub fn parse_file<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), ParseError> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the map is only read, but another process can still change
+        // or truncate the file while it is mapped. The lexer would then read
+        // changing bytes, or the process would get SIGBUS on a page past the
+        // new end of the file, which is why the documentation requires that
+        // the file isn't changed during the parse.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        #[cfg(unix)]
+        // Only a hint to the kernel, so the parse doesn't depend on it.
+        let _ = map.advise(memmap2::Advice::Sequential);
+        self.parse(&mut &map[..])
     }
 
     /// Parse has already returned from an EOF. This method checks that
@@ -264,7 +328,7 @@ impl<'a, H: Handler> Parser<'a, H> {
     ///
     /// # Errors
     ///    - Returns an error Result if the JSON was malformed.
-    pub fn finish_parse(self) -> Result<(), ParseError> {
+    pub fn finish_parse(&self) -> Result<(), ParseError> {
         if self.context.parser_status() != ParserStatus::ParseComplete {
             return Err(ParseError::MalformedJson(
                 "Did not reach a ParseComplete status".to_owned(),
@@ -289,24 +353,69 @@ impl<'a, H: Handler> Parser<'a, H> {
     }
 }
 
+/// An error in an element of the top-level array, that was skipped because
+/// of `ParserOptions::recover_elements`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ElementError {
+    /// The index of the element in the array.
+    pub index: usize,
+    /// The number of bytes read before the error was found.
+    pub offset: u64,
+    /// The error.
+    pub error: ParseError,
+}
+
+impl std::fmt::Display for ElementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Element {} at byte {}: {}",
+            self.index, self.offset, self.error
+        )
+    }
+}
+
+impl std::error::Error for ElementError {}
+
 /// `ParseError`
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Clone)]
 pub enum ParseError {
-    /// Bytes can't be decoded as UTF-8 to a string.
-    Utf8Error(String),
+    /// Bytes of a string or key can't be decoded as UTF-8, or UTF-16 or
+    /// UTF-32 input has an invalid character, such as an unpaired surrogate.
+    Utf8Error {
+        /// The offset of the invalid sequence in the input. For UTF-16 and
+        /// UTF-32 this is before transcoding.
+        offset: u64,
+        /// What is wrong with it.
+        message: String,
+    },
     /// The json is malformed in some way, missing closing brace, bracket, or
     /// a value can't be parsed due to being malformed.
     MalformedJson(String),
     /// Error of the underlying Reader.
     ReadError(String),
+    /// More objects and arrays were open at once than `ParserOptions::max_depth`.
+    DepthLimitExceeded(usize),
+    /// A string or key was longer than `ParserOptions::max_string_length`.
+    StringLengthExceeded(usize),
+    /// A number was longer than `ParserOptions::max_number_length`.
+    NumberLengthExceeded(usize),
+    /// More bytes were read than `ParserOptions::max_bytes`.
+    DocumentSizeExceeded(u64),
+    /// There were more values than `ParserOptions::max_elements`.
+    ElementCountExceeded(u64),
 }
 
 impl std::error::Error for ParseError {
     fn description(&self) -> &str {
         match self {
-            ParseError::Utf8Error(ref msg)
-            | ParseError::MalformedJson(ref msg)
-            | ParseError::ReadError(ref msg) => msg,
+            ParseError::Utf8Error { ref message, .. } => message,
+            ParseError::MalformedJson(ref msg) | ParseError::ReadError(ref msg) => msg,
+            ParseError::DepthLimitExceeded(_) => "maximum nesting depth exceeded",
+            ParseError::StringLengthExceeded(_) => "maximum string length exceeded",
+            ParseError::NumberLengthExceeded(_) => "maximum number length exceeded",
+            ParseError::DocumentSizeExceeded(_) => "maximum document size exceeded",
+            ParseError::ElementCountExceeded(_) => "maximum element count exceeded",
         }
     }
 
@@ -318,11 +427,31 @@ impl std::error::Error for ParseError {
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ParseError::Utf8Error(ref msg) => {
-                write!(f, "Error converting bytes to UTF-8 encoded string: {}", msg)
-            }
+            ParseError::Utf8Error {
+                offset,
+                ref message,
+            } => write!(
+                f,
+                "Error converting bytes to UTF-8 encoded string at byte {}: {}",
+                offset, message
+            ),
             ParseError::MalformedJson(ref msg) => write!(f, "Error: Malformed Json: {}", msg),
             ParseError::ReadError(ref msg) => write!(f, "Error: Read Error: {}", msg),
+            ParseError::DepthLimitExceeded(max) => {
+                write!(f, "Error: More than {} objects and arrays are open", max)
+            }
+            ParseError::StringLengthExceeded(max) => {
+                write!(f, "Error: A string is longer than {} bytes", max)
+            }
+            ParseError::NumberLengthExceeded(max) => {
+                write!(f, "Error: A number is longer than {} bytes", max)
+            }
+            ParseError::DocumentSizeExceeded(max) => {
+                write!(f, "Error: The document is longer than {} bytes", max)
+            }
+            ParseError::ElementCountExceeded(max) => {
+                write!(f, "Error: The document has more than {} values", max)
+            }
         }
     }
 }
@@ -335,37 +464,15 @@ impl std::fmt::Debug for ParseError {
 
 impl From<std::io::Error> for ParseError {
     fn from(other: std::io::Error) -> Self {
-        ParseError::ReadError(other.to_string())
-    }
-}
-
-fn update_context_status_value(context: &mut Context) {
-    if context.parser_status() == ParserStatus::ArrayNeedVal
-        || context.parser_status() == ParserStatus::ArrayStart
-    {
-        context.update_status(ParserStatus::ArrayGotVal);
-    } else if context.parser_status() == ParserStatus::MapNeedVal {
-        context.update_status(ParserStatus::MapGotVal);
-    } else if context.parser_status() == ParserStatus::Start {
-        context.update_status(ParserStatus::GotValue);
-    } else {
-        context.update_status(ParserStatus::LexicalError);
-    }
-}
-
-struct Wrapper<'a>(&'a mut dyn BufRead);
-
-impl<'a> Iterator for Wrapper<'a> {
-    type Item = u8;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let buffer = self.0.fill_buf().ok();
-        match buffer.map(|b| b.first()).flatten().copied() {
-            Some(b) => {
-                self.0.consume(1);
-                Some(b)
-            }
-            None => None,
+        match other
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<InvalidInput>())
+        {
+            Some(invalid) => ParseError::Utf8Error {
+                offset: invalid.offset,
+                message: invalid.to_string(),
+            },
+            None => ParseError::ReadError(other.to_string()),
         }
     }
 }
@@ -0,0 +1,297 @@
+//! Predicates over the values selected by an `NdJsonHandler`, used to
+//! only write some of them.
+//!
+//! ```text
+//! .properties.type == "Feature" && .year > 2000
+//! ```
+//!
+//! Requires feature `ndjson`.
+//!
+
+use crate::ndjson_handler::Selector;
+
+/// A condition on a JSON value.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Predicate {
+    /// The scalar at the path, relative to the value, compared with a literal.
+    /// False if there is no scalar at the path.
+    Compare(Vec<Selector>, Comparison, Literal),
+    /// Both predicates are true.
+    And(Box<Predicate>, Box<Predicate>),
+    /// Either predicate is true.
+    Or(Box<Predicate>, Box<Predicate>),
+    /// The predicate is false.
+    Not(Box<Predicate>),
+}
+
+/// Comparison operators.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Comparison {
+    /// `==`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+/// The right hand side of a comparison.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Literal {
+    /// `null`
+    Null,
+    /// `true` or `false`
+    Bool(bool),
+    /// Compared with both ints and doubles.
+    Number(f64),
+    /// The string without its quotes. Escapes are compared as written.
+    String(String),
+}
+
+/// A scalar value as it is passed to a `Handler`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Scalar<'a> {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    /// The string with its quotes.
+    String(&'a str),
+}
+
+/// A string or key as it is passed to a `Handler`, displayed with its
+/// control characters escaped, since JSON doesn't allow them in strings and
+/// a newline would end the line in NDJSON.
+pub(crate) struct Escaped<'a>(pub(crate) &'a str);
+
+impl std::fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut start = 0;
+        for (i, b) in self.0.bytes().enumerate() {
+            if b >= 0x20 {
+                continue;
+            }
+            f.write_str(&self.0[start..i])?;
+            match b {
+                b'\n' => f.write_str("\\n")?,
+                b'\r' => f.write_str("\\r")?,
+                b'\t' => f.write_str("\\t")?,
+                _ => write!(f, "\\u{:04x}", b)?,
+            }
+            start = i + 1;
+        }
+        f.write_str(&self.0[start..])
+    }
+}
+
+impl std::fmt::Display for Scalar<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scalar::Null => write!(f, "null"),
+            Scalar::Bool(val) => write!(f, "{}", val),
+            Scalar::Int(val) => write!(f, "{}", val),
+            // JSON has no NaN or infinity.
+            Scalar::Double(val) if !val.is_finite() => write!(f, "null"),
+            // Debug keeps the decimal point or exponent so it is still a double.
+            Scalar::Double(val) => write!(f, "{:?}", val),
+            Scalar::String(val) => write!(f, "{}", Escaped(val)),
+        }
+    }
+}
+
+impl Comparison {
+    fn holds(self, ordering: Option<std::cmp::Ordering>) -> bool {
+        use std::cmp::Ordering;
+
+        match (self, ordering) {
+            (Comparison::Eq, Some(Ordering::Equal))
+            | (Comparison::Lt, Some(Ordering::Less))
+            | (Comparison::Gt, Some(Ordering::Greater))
+            | (Comparison::Le, Some(Ordering::Less | Ordering::Equal))
+            | (Comparison::Ge, Some(Ordering::Greater | Ordering::Equal)) => true,
+            (Comparison::Ne, ordering) => ordering != Some(Ordering::Equal),
+            _ => false,
+        }
+    }
+}
+
+impl Literal {
+    /// Compare a scalar with this literal. Values of different types are
+    /// only ever not equal.
+    pub(crate) fn compare(&self, comparison: Comparison, val: Scalar<'_>) -> bool {
+        let ordering = match (self, val) {
+            (Literal::Null, Scalar::Null) => Some(std::cmp::Ordering::Equal),
+            (Literal::Bool(lit), Scalar::Bool(val)) => val.partial_cmp(lit),
+            (Literal::Number(lit), Scalar::Int(val)) => (val as f64).partial_cmp(lit),
+            (Literal::Number(lit), Scalar::Double(val)) => val.partial_cmp(lit),
+            (Literal::String(lit), Scalar::String(val)) => val
+                .get(1..val.len().saturating_sub(1))
+                .map(|val| val.cmp(lit.as_str())),
+            _ => None,
+        };
+        comparison.holds(ordering)
+    }
+}
+
+impl Predicate {
+    /// The comparisons in the order they are evaluated.
+    pub(crate) fn comparisons(&self) -> Vec<(&[Selector], Comparison, &Literal)> {
+        let mut comparisons = vec![];
+        self.push_comparisons(&mut comparisons);
+        comparisons
+    }
+
+    fn push_comparisons<'a>(
+        &'a self,
+        comparisons: &mut Vec<(&'a [Selector], Comparison, &'a Literal)>,
+    ) {
+        match self {
+            Predicate::Compare(path, comparison, literal) => {
+                comparisons.push((path, *comparison, literal));
+            }
+            Predicate::And(l, r) | Predicate::Or(l, r) => {
+                l.push_comparisons(comparisons);
+                r.push_comparisons(comparisons);
+            }
+            Predicate::Not(p) => p.push_comparisons(comparisons),
+        }
+    }
+
+    /// Evaluate with the result of each comparison, None if it is not known yet.
+    /// Returns None if the result depends on a comparison that is not known.
+    pub(crate) fn evaluate(&self, results: &[Option<bool>]) -> Option<bool> {
+        self.evaluate_from(results, &mut 0)
+    }
+
+    fn evaluate_from(&self, results: &[Option<bool>], i: &mut usize) -> Option<bool> {
+        match self {
+            Predicate::Compare(..) => {
+                *i += 1;
+                results[*i - 1]
+            }
+            Predicate::And(l, r) => {
+                let l = l.evaluate_from(results, i);
+                let r = r.evaluate_from(results, i);
+                match (l, r) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }
+            }
+            Predicate::Or(l, r) => {
+                let l = l.evaluate_from(results, i);
+                let r = r.evaluate_from(results, i);
+                match (l, r) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                }
+            }
+            Predicate::Not(p) => p.evaluate_from(results, i).map(|p| !p),
+        }
+    }
+}
+
+use lalrpop_util::lalrpop_mod;
+
+lalrpop_mod!(#[allow(clippy::all)] pub predicate_parser);
+
+pub use self::predicate_parser::{PathParser, PredicateParser};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_predicate_parser() {
+        assert_eq!(
+            PredicateParser::new().parse(".properties.type == \"Feature\""),
+            Ok(Predicate::Compare(
+                vec![
+                    Selector::Identifier("properties".into()),
+                    Selector::Identifier("type".into())
+                ],
+                Comparison::Eq,
+                Literal::String("Feature".into())
+            ))
+        );
+
+        assert_eq!(
+            PredicateParser::new().parse(". >= -2.5"),
+            Ok(Predicate::Compare(
+                vec![],
+                Comparison::Ge,
+                Literal::Number(-2.5)
+            ))
+        );
+
+        assert_eq!(
+            PredicateParser::new().parse("!(.a[2] != null) || .b == true && .c < 4"),
+            Ok(Predicate::Or(
+                Box::new(Predicate::Not(Box::new(Predicate::Compare(
+                    vec![Selector::Identifier("a".into()), Selector::Index(2)],
+                    Comparison::Ne,
+                    Literal::Null
+                )))),
+                Box::new(Predicate::And(
+                    Box::new(Predicate::Compare(
+                        vec![Selector::Identifier("b".into())],
+                        Comparison::Eq,
+                        Literal::Bool(true)
+                    )),
+                    Box::new(Predicate::Compare(
+                        vec![Selector::Identifier("c".into())],
+                        Comparison::Lt,
+                        Literal::Number(4.0)
+                    ))
+                ))
+            ))
+        );
+
+        assert!(PredicateParser::new().parse(".a ==").is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let year = Literal::Number(2000.0);
+        assert!(year.compare(Comparison::Gt, Scalar::Int(2001)));
+        assert!(!year.compare(Comparison::Gt, Scalar::Double(1999.5)));
+        assert!(!year.compare(Comparison::Gt, Scalar::String("\"2001\"")));
+        assert!(year.compare(Comparison::Ne, Scalar::Null));
+
+        let feature = Literal::String("Feature".into());
+        assert!(feature.compare(Comparison::Eq, Scalar::String("\"Feature\"")));
+        assert!(feature.compare(Comparison::Lt, Scalar::String("\"Apple\"")));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Scalar::Double(0.0).to_string(), "0.0");
+        assert_eq!(Scalar::Double(f64::NAN).to_string(), "null");
+        assert_eq!(Scalar::Double(f64::INFINITY).to_string(), "null");
+        assert_eq!(Scalar::Double(f64::NEG_INFINITY).to_string(), "null");
+        assert_eq!(
+            Scalar::String("\"a\nb\t\u{1}\\n\"").to_string(),
+            r#""a\nb\t\u0001\n""#
+        );
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let predicate = PredicateParser::new()
+            .parse(".a == 1 && !(.b == 2)")
+            .unwrap();
+        assert_eq!(predicate.comparisons().len(), 2);
+        assert_eq!(predicate.evaluate(&[None, None]), None);
+        assert_eq!(predicate.evaluate(&[Some(false), None]), Some(false));
+        assert_eq!(predicate.evaluate(&[Some(true), None]), None);
+        assert_eq!(predicate.evaluate(&[Some(true), Some(false)]), Some(true));
+    }
+}
@@ -0,0 +1,408 @@
+//! A writer that splits NDJSON into numbered files of at most a number of
+//! lines or bytes, optionally compressing each one on its own thread. Give
+//! it, or a `&mut` to it, to an `NdJsonHandler` as the writer of a route.
+//!
+//! ```no_run
+//!     use yajlish::{rotating_writer::RotatingWriter, Compression};
+//!     use std::io::Write;
+//!
+//!     // out/part-00001.ndjson.gz, out/part-00002.ndjson.gz, ...
+//!     let mut writer = RotatingWriter::new("out")
+//!         .max_lines(1_000_000)
+//!         .compress(Compression::Gzip);
+//!     writer.write_all(b"{\"a\":1}\n").unwrap();
+//!     let paths = writer.finish().unwrap();
+//! ```
+
+use crate::Compression;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    mem,
+    path::PathBuf,
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+/// The size of the blocks that are sent to a compression thread.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The number of blocks that can wait for a compression thread, before
+/// writes wait for it.
+const CHUNKS_QUEUED: usize = 4;
+
+/// Writes lines to a sequence of files named
+/// `<prefix>-<number>.ndjson<extension>`, starting a new file after a line
+/// when the current one has reached `max_lines` or `max_bytes`. Files are
+/// only split after a newline, which `NdJsonHandler` only writes at the end
+/// of each value, since it escapes newlines in strings.
+///
+/// Call `finish` to finish the last file and wait for the compression
+/// threads; otherwise it is done on drop, ignoring errors.
+pub struct RotatingWriter {
+    dir: PathBuf,
+    prefix: String,
+    max_lines: Option<u64>,
+    max_bytes: Option<u64>,
+    compression: Compression,
+    threads: usize,
+
+    part: Option<Part>,
+    lines: u64,
+    bytes: u64,
+    paths: Vec<PathBuf>,
+    /// The compression threads of earlier files that may still be running.
+    workers: VecDeque<JoinHandle<io::Result<()>>>,
+}
+
+/// The file being written.
+enum Part {
+    File(BufWriter<File>),
+    /// Blocks of the file are sent to a thread that compresses them.
+    Compressed {
+        buffer: Vec<u8>,
+        chunks: SyncSender<Vec<u8>>,
+        worker: JoinHandle<io::Result<()>>,
+    },
+}
+
+impl RotatingWriter {
+    /// Constructor, for files in the directory `dir`. The directory has to
+    /// exist.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        RotatingWriter {
+            dir: dir.into(),
+            prefix: "part".to_owned(),
+            max_lines: None,
+            max_bytes: None,
+            compression: Compression::None,
+            threads: thread::available_parallelism().map_or(1, usize::from),
+            part: None,
+            lines: 0,
+            bytes: 0,
+            paths: vec![],
+            workers: VecDeque::new(),
+        }
+    }
+
+    /// The start of each file name, `part` by default.
+    #[must_use]
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// Start a new file once this many lines have been written to one.
+    #[must_use]
+    pub fn max_lines(mut self, max_lines: u64) -> Self {
+        self.max_lines = Some(max_lines);
+        self
+    }
+
+    /// Start a new file after the line that reaches this many bytes, before
+    /// compression.
+    #[must_use]
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Compress each file, which requires the compression's feature. Files
+    /// are compressed on their own threads, while the next ones are written.
+    #[must_use]
+    pub fn compress(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// The number of files that are compressed at once, the number of CPUs
+    /// by default.
+    #[must_use]
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Finish the last file, and wait for the files to be compressed.
+    /// Returns the paths of the files, in order.
+    ///
+    /// # Errors
+    ///    - Will return an error Result if a file can't be written.
+    pub fn finish(mut self) -> io::Result<Vec<PathBuf>> {
+        self.close()?;
+        Ok(mem::take(&mut self.paths))
+    }
+
+    fn open_part(&mut self) -> io::Result<()> {
+        if !self.compression.is_enabled() {
+            return Err(self.compression.unsupported());
+        }
+        let path = self.dir.join(format!(
+            "{}-{:05}.ndjson{}",
+            self.prefix,
+            self.paths.len() + 1,
+            self.compression.extension()
+        ));
+        let file = File::create(&path)?;
+        self.part = Some(match self.compression {
+            Compression::None => Part::File(BufWriter::new(file)),
+            compression => {
+                let (chunks, receiver) = sync_channel(CHUNKS_QUEUED);
+                Part::Compressed {
+                    buffer: Vec::with_capacity(CHUNK_SIZE),
+                    chunks,
+                    worker: thread::spawn(move || compress(compression, file, receiver)),
+                }
+            }
+        });
+        self.paths.push(path);
+        self.lines = 0;
+        self.bytes = 0;
+        Ok(())
+    }
+
+    /// Finish the current file. Waits for the oldest compression thread
+    /// when `threads` of them are running.
+    fn close_part(&mut self) -> io::Result<()> {
+        match self.part.take() {
+            Some(Part::File(mut file)) => file.flush()?,
+            Some(Part::Compressed {
+                buffer,
+                chunks,
+                worker,
+            }) => {
+                // An error is returned by the worker.
+                let _ = chunks.send(buffer);
+                drop(chunks);
+                self.workers.push_back(worker);
+                while self.workers.len() >= self.threads {
+                    if let Some(worker) = self.workers.pop_front() {
+                        join(worker)?;
+                    }
+                }
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let closed = self.close_part();
+        let mut result = Ok(());
+        for worker in self.workers.drain(..) {
+            result = result.and(join(worker));
+        }
+        closed.and(result)
+    }
+}
+
+impl Write for RotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.part.is_none() {
+            self.open_part()?;
+        }
+        // Write up to the end of a line, so that the file can be split there.
+        let newline = buf.iter().position(|b| *b == b'\n');
+        let n = newline.map_or(buf.len(), |i| i + 1);
+        let sent = match &mut self.part {
+            Some(Part::File(file)) => {
+                file.write_all(&buf[..n])?;
+                true
+            }
+            Some(Part::Compressed { buffer, chunks, .. }) => {
+                buffer.extend_from_slice(&buf[..n]);
+                buffer.len() < CHUNK_SIZE
+                    || chunks
+                        .send(mem::replace(buffer, Vec::with_capacity(CHUNK_SIZE)))
+                        .is_ok()
+            }
+            None => unreachable!("a part was opened"),
+        };
+        if !sent {
+            // The worker stopped because of an error.
+            if let Some(Part::Compressed { worker, .. }) = self.part.take() {
+                join(worker)?;
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "compression thread stopped",
+            ));
+        }
+        self.bytes += n as u64;
+        if newline.is_some() {
+            self.lines += 1;
+            let full = self.max_lines.is_some_and(|max| self.lines >= max)
+                || self.max_bytes.is_some_and(|max| self.bytes >= max);
+            if full {
+                self.close_part()?;
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.part {
+            Some(Part::File(file)) => file.flush(),
+            Some(Part::Compressed { .. }) | None => Ok(()),
+        }
+    }
+}
+
+impl Drop for RotatingWriter {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+fn join(worker: JoinHandle<io::Result<()>>) -> io::Result<()> {
+    worker
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::other("compression thread panicked")))
+}
+
+/// Compress the chunks to the file, until the sender is dropped.
+#[cfg_attr(
+    not(any(feature = "gzip", feature = "zstd", feature = "bzip2")),
+    allow(unused_variables)
+)]
+fn compress(compression: Compression, file: File, chunks: Receiver<Vec<u8>>) -> io::Result<()> {
+    let file = BufWriter::new(file);
+    let write_chunks = |out: &mut dyn Write| -> io::Result<()> {
+        for chunk in chunks {
+            out.write_all(&chunk)?;
+        }
+        Ok(())
+    };
+    match compression {
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            write_chunks(&mut encoder)?;
+            encoder.finish()?.flush()
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            let mut encoder = zstd::stream::write::Encoder::new(file, 0)?;
+            write_chunks(&mut encoder)?;
+            encoder.finish()?.flush()
+        }
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => {
+            let mut encoder = bzip2::write::BzEncoder::new(file, bzip2::Compression::default());
+            write_chunks(&mut encoder)?;
+            encoder.finish()?.flush()
+        }
+        _ => unreachable!("only enabled compressions have threads"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    /// An empty directory for a test.
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("yajlish-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read(paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| {
+                let mut out = String::new();
+                let mut read = crate::open(path).unwrap();
+                io::Read::read_to_string(&mut read, &mut out).unwrap();
+                out
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_max_lines() {
+        let dir = dir("max-lines");
+        let mut writer = RotatingWriter::new(&dir).max_lines(2);
+        // Lines are split between writes.
+        for part in ["1\n2", "\n3\n", "4\n5", "\n"] {
+            writer.write_all(part.as_bytes()).unwrap();
+        }
+        let paths = writer.finish().unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                dir.join("part-00001.ndjson"),
+                dir.join("part-00002.ndjson"),
+                dir.join("part-00003.ndjson"),
+            ]
+        );
+        assert_eq!(read(&paths), vec!["1\n2\n", "3\n4\n", "5\n"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let dir = dir("max-bytes");
+        let mut writer = RotatingWriter::new(&dir).prefix("rows").max_bytes(4);
+        writer.write_all(b"1\n22\n333\n4\n").unwrap();
+        let paths = writer.finish().unwrap();
+        assert_eq!(paths[0], dir.join("rows-00001.ndjson"));
+        assert_eq!(read(&paths), vec!["1\n22\n", "333\n", "4\n"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn test_unsupported() {
+        let mut writer = RotatingWriter::new(std::env::temp_dir()).compress(Compression::Gzip);
+        assert_eq!(
+            writer.write_all(b"1\n").unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+
+    #[cfg(all(feature = "ndjson", feature = "gzip"))]
+    #[test]
+    fn test_ndjson_gzip() {
+        use crate::{ndjson_handler::NdJsonHandler, Parser};
+
+        let dir = dir("ndjson-gzip");
+        let mut writer = RotatingWriter::new(&dir)
+            .max_lines(2)
+            .compress(Compression::Gzip)
+            .threads(2);
+        let mut handler = NdJsonHandler::new(&mut writer, vec![]);
+        Parser::new(&mut handler)
+            .parse(&mut "[{ \"a\": 1 }, [2], \"3\", 4, 5]".as_bytes())
+            .unwrap();
+        drop(handler);
+        let paths = writer.finish().unwrap();
+        assert_eq!(paths[2], dir.join("part-00003.ndjson.gz"));
+        assert_eq!(read(&paths), vec!["{\"a\":1}\n[2]\n", "\"3\"\n4\n", "5\n"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "ndjson")]
+    #[test]
+    fn test_ndjson_newline_in_string() {
+        use crate::{ndjson_handler::NdJsonHandler, Parser};
+
+        let dir = dir("ndjson-newline");
+        let mut writer = RotatingWriter::new(&dir).max_lines(1);
+        let mut handler = NdJsonHandler::new(&mut writer, vec![]);
+        Parser::new(&mut handler)
+            .parse(&mut "[\"a\nb\", { \"c\nd\": 2 }]".as_bytes())
+            .unwrap();
+        drop(handler);
+        let paths = writer.finish().unwrap();
+        assert_eq!(read(&paths), vec!["\"a\\nb\"\n", "{\"c\\nd\":2}\n"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+//! This Handler implementation infers a JSON Schema (draft 2020-12)
+//! from the values in a document.
+//!
+//! Every path is summarized as it is parsed: array items share one schema,
+//! and each object has at most `max_keys` properties, so memory depends on
+//! the shape of the document and not its size.
+//!
+//! Besides the standard keywords, the schema has the annotations
+//! `x-keyFrequency`, the number of objects each key was in, and
+//! `x-lengths`, a histogram of array lengths in power-of-two buckets.
+//!
+
+use crate::{common::unescape, value::Value, Context, Handler, Status};
+
+const SCHEMA: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// The default of `SchemaHandler::max_keys`.
+pub const DEFAULT_MAX_KEYS: usize = 1000;
+
+/// Infers a JSON Schema.
+#[derive(Debug)]
+pub struct SchemaHandler {
+    nodes: Vec<Node>,
+    stack: Vec<Frame>,
+    max_keys: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Null,
+    Boolean,
+    Integer,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl Type {
+    const ALL: [Type; 7] = [
+        Type::Null,
+        Type::Boolean,
+        Type::Integer,
+        Type::Number,
+        Type::String,
+        Type::Array,
+        Type::Object,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Type::Null => "null",
+            Type::Boolean => "boolean",
+            Type::Integer => "integer",
+            Type::Number => "number",
+            Type::String => "string",
+            Type::Array => "array",
+            Type::Object => "object",
+        }
+    }
+}
+
+/// Everything observed at one path.
+#[derive(Debug, Default)]
+struct Node {
+    /// The number of values of each `Type`.
+    types: [u64; 7],
+    int_range: Option<(i64, i64)>,
+    double_range: Option<(f64, f64)>,
+    length_range: Option<(usize, usize)>,
+    items_range: Option<(usize, usize)>,
+    /// The number of arrays with a length in each power-of-two bucket.
+    lengths: Vec<u64>,
+    items: Option<usize>,
+    /// The unescaped key, its node and the number of objects it was in.
+    properties: Vec<(String, usize, u64)>,
+    /// The values of keys after `max_keys`.
+    additional: Option<usize>,
+}
+
+impl Node {
+    fn count(&self, t: Type) -> u64 {
+        self.types[t as usize]
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    node: usize,
+    /// The node of the current member of an object.
+    member: Option<usize>,
+    len: usize,
+}
+
+fn extend<T: PartialOrd + Copy>(range: &mut Option<(T, T)>, val: T) {
+    *range = Some(match *range {
+        Some((min, max)) => (
+            if val < min { val } else { min },
+            if val > max { val } else { max },
+        ),
+        None => (val, val),
+    });
+}
+
+fn bucket(len: usize) -> usize {
+    (usize::BITS - len.leading_zeros()) as usize
+}
+
+fn bucket_name(bucket: usize) -> String {
+    if bucket <= 1 {
+        return bucket.to_string();
+    }
+    let low = 1usize << (bucket - 1);
+    let high = low.wrapping_shl(1).wrapping_sub(1);
+    format!("{}-{}", low, high)
+}
+
+impl Default for SchemaHandler {
+    fn default() -> Self {
+        SchemaHandler::new()
+    }
+}
+
+impl SchemaHandler {
+    /// Constructor.
+    pub fn new() -> Self {
+        SchemaHandler {
+            nodes: vec![Node::default()],
+            stack: vec![],
+            max_keys: DEFAULT_MAX_KEYS,
+        }
+    }
+
+    /// The number of keys that get their own property schema in each object.
+    /// Keys after that are summarized together in `additionalProperties`.
+    #[must_use]
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    /// The inferred schema as JSON.
+    pub fn schema(&self) -> String {
+        match self.node_schema(0) {
+            Value::Object(mut members) => {
+                members.insert(0, ("$schema".to_owned(), Value::from(SCHEMA)));
+                Value::Object(members).to_string()
+            }
+            schema => schema.to_string(),
+        }
+    }
+
+    fn new_node(&mut self) -> usize {
+        self.nodes.push(Node::default());
+        self.nodes.len() - 1
+    }
+
+    /// The node of the value that is starting.
+    fn enter_value(&mut self) -> usize {
+        match self.stack.last_mut() {
+            None => 0,
+            Some(frame) => {
+                frame.len += 1;
+                if let Some(member) = frame.member.take() {
+                    return member;
+                }
+                let parent = frame.node;
+                match self.nodes[parent].items {
+                    Some(items) => items,
+                    None => {
+                        let items = self.new_node();
+                        self.nodes[parent].items = Some(items);
+                        items
+                    }
+                }
+            }
+        }
+    }
+
+    fn observe(&mut self, t: Type) -> usize {
+        let node = self.enter_value();
+        self.nodes[node].types[t as usize] += 1;
+        node
+    }
+
+    fn start(&mut self, t: Type) -> Status {
+        let node = self.observe(t);
+        self.stack.push(Frame {
+            node,
+            member: None,
+            len: 0,
+        });
+        Status::Continue
+    }
+
+    fn end_map(&mut self) -> Status {
+        self.stack.pop();
+        Status::Continue
+    }
+
+    fn end_array(&mut self) -> Status {
+        if let Some(frame) = self.stack.pop() {
+            let node = &mut self.nodes[frame.node];
+            extend(&mut node.items_range, frame.len);
+            let bucket = bucket(frame.len);
+            if node.lengths.len() <= bucket {
+                node.lengths.resize(bucket + 1, 0);
+            }
+            node.lengths[bucket] += 1;
+        }
+        Status::Continue
+    }
+
+    fn map_key(&mut self, key: &str) -> Status {
+        let key = unescape(key).unwrap_or_else(|| key.to_owned());
+        let max_keys = self.max_keys;
+        let parent = match self.stack.last() {
+            Some(frame) => frame.node,
+            None => return Status::Continue,
+        };
+
+        let properties = &mut self.nodes[parent].properties;
+        let member = match properties.iter().position(|(k, _, _)| *k == key) {
+            Some(i) => {
+                properties[i].2 += 1;
+                properties[i].1
+            }
+            None if properties.len() < max_keys => {
+                let node = self.new_node();
+                self.nodes[parent].properties.push((key, node, 1));
+                node
+            }
+            None => match self.nodes[parent].additional {
+                Some(node) => node,
+                None => {
+                    let node = self.new_node();
+                    self.nodes[parent].additional = Some(node);
+                    node
+                }
+            },
+        };
+        if let Some(frame) = self.stack.last_mut() {
+            frame.member = Some(member);
+        }
+        Status::Continue
+    }
+
+    fn node_schema(&self, index: usize) -> Value {
+        let node = &self.nodes[index];
+        let mut schema = vec![];
+
+        let mut types: Vec<Type> = Type::ALL
+            .iter()
+            .copied()
+            .filter(|t| node.count(*t) > 0)
+            .collect();
+        if types.contains(&Type::Integer) && types.contains(&Type::Number) {
+            types.retain(|t| *t != Type::Integer);
+        }
+        match types.as_slice() {
+            [] => (),
+            [t] => schema.push(("type".to_owned(), Value::from(t.name()))),
+            types => schema.push((
+                "type".to_owned(),
+                Value::Array(types.iter().map(|t| Value::from(t.name())).collect()),
+            )),
+        }
+
+        match (node.int_range, node.double_range) {
+            (Some((min, max)), None) => {
+                schema.push(("minimum".to_owned(), Value::Int(min)));
+                schema.push(("maximum".to_owned(), Value::Int(max)));
+            }
+            (ints, Some((min, max))) => {
+                let (min, max) = match ints {
+                    Some((i, j)) => (min.min(i as f64), max.max(j as f64)),
+                    None => (min, max),
+                };
+                schema.push(("minimum".to_owned(), Value::Double(min)));
+                schema.push(("maximum".to_owned(), Value::Double(max)));
+            }
+            (None, None) => (),
+        }
+
+        if let Some((min, max)) = node.length_range {
+            schema.push(("minLength".to_owned(), Value::from(min as u64)));
+            schema.push(("maxLength".to_owned(), Value::from(max as u64)));
+        }
+
+        if let Some((min, max)) = node.items_range {
+            if let Some(items) = node.items {
+                schema.push(("items".to_owned(), self.node_schema(items)));
+            }
+            schema.push(("minItems".to_owned(), Value::from(min as u64)));
+            schema.push(("maxItems".to_owned(), Value::from(max as u64)));
+            schema.push((
+                "x-lengths".to_owned(),
+                Value::Object(
+                    node.lengths
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, count)| **count > 0)
+                        .map(|(bucket, count)| (bucket_name(bucket), Value::from(*count)))
+                        .collect(),
+                ),
+            ));
+        }
+
+        let objects = node.count(Type::Object);
+        if objects > 0 {
+            schema.push((
+                "properties".to_owned(),
+                Value::Object(
+                    node.properties
+                        .iter()
+                        .map(|(key, index, _)| (key.clone(), self.node_schema(*index)))
+                        .collect(),
+                ),
+            ));
+            schema.push((
+                "required".to_owned(),
+                Value::Array(
+                    node.properties
+                        .iter()
+                        .filter(|(_, _, count)| *count >= objects)
+                        .map(|(key, _, _)| Value::from(key.as_str()))
+                        .collect(),
+                ),
+            ));
+            if let Some(additional) = node.additional {
+                schema.push((
+                    "additionalProperties".to_owned(),
+                    self.node_schema(additional),
+                ));
+            }
+            schema.push((
+                "x-keyFrequency".to_owned(),
+                Value::Object(
+                    node.properties
+                        .iter()
+                        .map(|(key, _, count)| (key.clone(), Value::from(*count)))
+                        .collect(),
+                ),
+            ));
+        }
+
+        Value::Object(schema)
+    }
+}
+
+impl Handler for SchemaHandler {
+    fn handle_null(&mut self, _ctx: &Context) -> Status {
+        self.observe(Type::Null);
+        Status::Continue
+    }
+
+    fn handle_double(&mut self, _ctx: &Context, val: f64) -> Status {
+        let node = self.observe(Type::Number);
+        extend(&mut self.nodes[node].double_range, val);
+        Status::Continue
+    }
+
+    fn handle_int(&mut self, _ctx: &Context, val: i64) -> Status {
+        let node = self.observe(Type::Integer);
+        extend(&mut self.nodes[node].int_range, val);
+        Status::Continue
+    }
+
+    fn handle_bool(&mut self, _ctx: &Context, _val: bool) -> Status {
+        self.observe(Type::Boolean);
+        Status::Continue
+    }
+
+    fn handle_string(&mut self, _ctx: &Context, val: &str) -> Status {
+        let node = self.observe(Type::String);
+        let len = unescape(val).map_or(val.len(), |s| s.chars().count());
+        extend(&mut self.nodes[node].length_range, len);
+        Status::Continue
+    }
+
+    fn handle_start_map(&mut self, _ctx: &Context) -> Status {
+        self.start(Type::Object)
+    }
+
+    fn handle_end_map(&mut self, _ctx: &Context) -> Status {
+        self.end_map()
+    }
+
+    fn handle_map_key(&mut self, _ctx: &Context, key: &str) -> Status {
+        self.map_key(key)
+    }
+
+    fn handle_start_array(&mut self, _ctx: &Context) -> Status {
+        self.start(Type::Array)
+    }
+
+    fn handle_end_array(&mut self, _ctx: &Context) -> Status {
+        self.end_array()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+    use pretty_assertions::assert_eq;
+
+    fn infer(input: &str, handler: SchemaHandler) -> String {
+        let mut handler = handler;
+        let mut parser = Parser::new(&mut handler);
+        parser.parse(&mut input.as_bytes()).unwrap();
+        handler.schema()
+    }
+
+    #[test]
+    fn test_infer_schema() {
+        let input = r#"[
+            { "id": 1, "name": "aé", "tags": [] },
+            { "id": 2.5, "name": null, "tags": ["x", "yz", "w"] },
+            { "id": -3, "tags": ["v"] }
+        ]"#;
+        assert_eq!(
+            infer(input, SchemaHandler::new()),
+            concat!(
+                r#"{"$schema":"https://json-schema.org/draft/2020-12/schema","type":"array","#,
+                r#""items":{"type":"object","properties":{"#,
+                r#""id":{"type":"number","minimum":-3.0,"maximum":2.5},"#,
+                r#""name":{"type":["null","string"],"minLength":2,"maxLength":2},"#,
+                r#""tags":{"type":"array","items":{"type":"string","minLength":1,"maxLength":2},"#,
+                r#""minItems":0,"maxItems":3,"x-lengths":{"0":1,"1":1,"2-3":1}}},"#,
+                r#""required":["id","tags"],"x-keyFrequency":{"id":3,"name":2,"tags":3}},"#,
+                r#""minItems":3,"maxItems":3,"x-lengths":{"2-3":1}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_max_keys() {
+        assert_eq!(
+            infer(
+                r#"{ "a": 1, "b": true, "c": "x" }"#,
+                SchemaHandler::new().max_keys(1)
+            ),
+            concat!(
+                r#"{"$schema":"https://json-schema.org/draft/2020-12/schema","type":"object","#,
+                r#""properties":{"a":{"type":"integer","minimum":1,"maximum":1}},"required":["a"],"#,
+                r#""additionalProperties":{"type":["boolean","string"],"minLength":1,"maxLength":1},"#,
+                r#""x-keyFrequency":{"a":1}}"#
+            )
+        );
+    }
+}
@@ -0,0 +1,694 @@
+//! This Handler implementation validates a document against a JSON Schema
+//! as it is parsed, without loading the document.
+//!
+//! The keywords `type`, `properties`, `required`, `items`, `enum`,
+//! `minimum`, `maximum`, `pattern` and `additionalProperties` are checked,
+//! other keywords are ignored. Each violation has the path of the value,
+//! e.g. `.features[2].id`.
+//!
+//! Requires feature `validation`.
+//!
+
+use crate::{
+    common::unescape,
+    value::{Value, ValueBuilder},
+    Context, Handler, Parser, Status,
+};
+use regex::Regex;
+use std::{io::BufRead, str::FromStr};
+
+/// A compiled JSON Schema.
+#[derive(Debug)]
+pub struct Schema {
+    nodes: Vec<Node>,
+}
+
+/// Error loading a `Schema`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SchemaError {
+    /// The schema is not valid JSON.
+    Json(String),
+    /// A keyword has a value that is not allowed.
+    Invalid(String),
+}
+
+impl std::error::Error for SchemaError {}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::Json(e) => write!(f, "Error: schema is not valid JSON: {}", e),
+            SchemaError::Invalid(e) => write!(f, "Error: invalid schema: {}", e),
+        }
+    }
+}
+
+/// A value that does not match its schema.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Violation {
+    /// The path of the value, `.` for the document.
+    pub path: String,
+    /// The keyword that failed.
+    pub keyword: &'static str,
+    /// What was wrong.
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} ({})", self.path, self.message, self.keyword)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Null,
+    Boolean,
+    Integer,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl Type {
+    fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "null" => Some(Type::Null),
+            "boolean" => Some(Type::Boolean),
+            "integer" => Some(Type::Integer),
+            "number" => Some(Type::Number),
+            "string" => Some(Type::String),
+            "array" => Some(Type::Array),
+            "object" => Some(Type::Object),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Type::Null => "null",
+            Type::Boolean => "boolean",
+            Type::Integer => "integer",
+            Type::Number => "number",
+            Type::String => "string",
+            Type::Array => "array",
+            Type::Object => "object",
+        }
+    }
+
+    fn of(val: &Value) -> Type {
+        match val {
+            Value::Null => Type::Null,
+            Value::Bool(_) => Type::Boolean,
+            Value::Int(_) => Type::Integer,
+            Value::Double(_) => Type::Number,
+            Value::String(_) => Type::String,
+            Value::Array(_) => Type::Array,
+            Value::Object(_) => Type::Object,
+        }
+    }
+
+    /// Integers are numbers, and doubles without a fraction are integers.
+    fn matches(self, actual: Type, val: Option<&Value>) -> bool {
+        self == actual
+            || (self == Type::Number && actual == Type::Integer)
+            || (self == Type::Integer && matches!(val, Some(Value::Double(d)) if d.fract() == 0.0))
+    }
+}
+
+/// One schema, the subschemas are indexes into `Schema::nodes`.
+#[derive(Debug, Default)]
+struct Node {
+    /// The `false` schema.
+    reject: bool,
+    types: Option<Vec<Type>>,
+    properties: Vec<(String, usize)>,
+    required: Vec<String>,
+    additional: Option<usize>,
+    items: Option<usize>,
+    enumeration: Option<Vec<Value>>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    pattern: Option<Regex>,
+}
+
+fn number(val: &Value) -> Option<f64> {
+    match val {
+        Value::Int(i) => Some(*i as f64),
+        Value::Double(d) => Some(*d),
+        _ => None,
+    }
+}
+
+/// JSON equality: numbers compare by value and members in any order.
+fn json_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_eq(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter().all(|(key, a)| {
+                    b.iter()
+                        .find(|(k, _)| k == key)
+                        .is_some_and(|(_, b)| json_eq(a, b))
+                })
+        }
+        (a, b) => match (number(a), number(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => a == b,
+        },
+    }
+}
+
+impl Schema {
+    /// Load a schema from JSON.
+    ///
+    /// # Errors
+    ///    - Returns an error if the schema is not valid JSON or uses a keyword
+    ///      with a value that is not allowed.
+    pub fn from_reader<B: BufRead>(read: &mut B) -> Result<Self, SchemaError> {
+        let mut builder = ValueBuilder::default();
+        let mut parser = Parser::new(&mut builder);
+        parser
+            .parse(read)
+            .and_then(|_| parser.finish_parse())
+            .map_err(|e| SchemaError::Json(e.to_string()))?;
+        let value = builder
+            .into_value()
+            .ok_or_else(|| SchemaError::Json("No value".to_owned()))?;
+
+        let mut schema = Schema { nodes: vec![] };
+        schema.compile(&value)?;
+        Ok(schema)
+    }
+
+    fn compile(&mut self, value: &Value) -> Result<usize, SchemaError> {
+        let index = self.nodes.len();
+        self.nodes.push(Node::default());
+
+        let members = match value {
+            Value::Bool(b) => {
+                self.nodes[index].reject = !b;
+                return Ok(index);
+            }
+            Value::Object(members) => members,
+            _ => return Err(SchemaError::Invalid(format!("{} is not a schema", value))),
+        };
+
+        let mut node = Node::default();
+        for (keyword, val) in members {
+            match (keyword.as_str(), val) {
+                ("type", Value::String(name)) => {
+                    node.types = Some(vec![type_name(name)?]);
+                }
+                ("type", Value::Array(names)) => {
+                    node.types = Some(
+                        names
+                            .iter()
+                            .map(|name| match name {
+                                Value::String(name) => type_name(name),
+                                _ => Err(invalid(keyword, name)),
+                            })
+                            .collect::<Result<_, _>>()?,
+                    );
+                }
+                ("properties", Value::Object(properties)) => {
+                    for (key, schema) in properties {
+                        let child = self.compile(schema)?;
+                        node.properties.push((key.clone(), child));
+                    }
+                }
+                ("required", Value::Array(keys)) => {
+                    node.required = keys
+                        .iter()
+                        .map(|key| match key {
+                            Value::String(key) => Ok(key.clone()),
+                            _ => Err(invalid(keyword, key)),
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                ("additionalProperties", schema) => {
+                    node.additional = Some(self.compile(schema)?);
+                }
+                ("items", schema) => node.items = Some(self.compile(schema)?),
+                ("enum", Value::Array(values)) => node.enumeration = Some(values.clone()),
+                ("minimum", val) if number(val).is_some() => node.minimum = number(val),
+                ("maximum", val) if number(val).is_some() => node.maximum = number(val),
+                ("pattern", Value::String(pattern)) => {
+                    node.pattern = Some(Regex::new(pattern).map_err(|e| {
+                        SchemaError::Invalid(format!("pattern {:?}: {}", pattern, e))
+                    })?);
+                }
+                (
+                    "type" | "properties" | "required" | "enum" | "minimum" | "maximum" | "pattern",
+                    val,
+                ) => return Err(invalid(keyword, val)),
+                _ => (),
+            }
+        }
+        self.nodes[index] = node;
+        Ok(index)
+    }
+}
+
+fn type_name(name: &str) -> Result<Type, SchemaError> {
+    Type::from_name(name).ok_or_else(|| SchemaError::Invalid(format!("unknown type {:?}", name)))
+}
+
+fn invalid(keyword: &str, val: &Value) -> SchemaError {
+    SchemaError::Invalid(format!("{} can't be {}", keyword, val))
+}
+
+impl FromStr for Schema {
+    type Err = SchemaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Schema::from_reader(&mut s.as_bytes())
+    }
+}
+
+/// An open object or array in the document.
+#[derive(Debug)]
+struct Frame {
+    node: Option<usize>,
+    is_object: bool,
+    /// The current key in an object.
+    key: String,
+    /// The number of values in an array.
+    count: usize,
+    /// The schema of the current member of an object.
+    member: Option<usize>,
+    /// Which of the node's required keys have been seen.
+    seen: Vec<bool>,
+}
+
+/// Validates the document against a `Schema`, and optionally passes each
+/// event on to another `Handler`.
+pub struct ValidatingHandler<'a> {
+    schema: Schema,
+    inner: Option<&'a mut dyn Handler>,
+    stack: Vec<Frame>,
+    /// Values with an `enum` that are being built, with their depth and node.
+    captures: Vec<(usize, usize, ValueBuilder)>,
+    violations: Vec<Violation>,
+}
+
+impl<'a> ValidatingHandler<'a> {
+    /// Constructor.
+    pub fn new(schema: Schema) -> Self {
+        ValidatingHandler {
+            schema,
+            inner: None,
+            stack: vec![],
+            captures: vec![],
+            violations: vec![],
+        }
+    }
+
+    /// Pass every event on to `handler`. Its `Status` is returned, so it can
+    /// still abort the parse, except that `Status::SkipValue` is returned as
+    /// `Status::Continue`: every value is validated, so `handler` still gets
+    /// the events of a value it asked to skip.
+    #[must_use]
+    pub fn forward_to(mut self, handler: &'a mut dyn Handler) -> Self {
+        self.inner = Some(handler);
+        self
+    }
+
+    /// The violations so far, in document order.
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// True if there are no violations so far.
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// The path of the current value.
+    fn path(&self) -> String {
+        if self.stack.is_empty() {
+            return ".".to_owned();
+        }
+        self.stack
+            .iter()
+            .map(|frame| {
+                if frame.is_object {
+                    format!(".{}", frame.key)
+                } else {
+                    format!("[{}]", frame.count.saturating_sub(1))
+                }
+            })
+            .collect()
+    }
+
+    fn violation(&mut self, keyword: &'static str, message: String) {
+        let path = self.path();
+        self.violations.push(Violation {
+            path,
+            keyword,
+            message,
+        });
+    }
+
+    /// The schema of the value that is starting.
+    fn enter_value(&mut self) -> Option<usize> {
+        let node = match self.stack.last_mut() {
+            None => Some(0),
+            Some(frame) if frame.is_object => frame.member.take(),
+            Some(frame) => {
+                frame.count += 1;
+                let parent = frame.node;
+                parent.and_then(|p| self.schema.nodes[p].items)
+            }
+        }?;
+        if self.schema.nodes[node].reject {
+            self.violation("false", "no value is allowed".to_owned());
+            return None;
+        }
+        Some(node)
+    }
+
+    fn check_type(&mut self, node: usize, actual: Type, val: Option<&Value>) {
+        if let Some(types) = &self.schema.nodes[node].types {
+            if !types.iter().any(|t| t.matches(actual, val)) {
+                let expected: Vec<&str> = types.iter().map(|t| t.name()).collect();
+                let message = format!(
+                    "expected {}, found {}",
+                    expected.join(" or "),
+                    actual.name()
+                );
+                self.violation("type", message);
+            }
+        }
+    }
+
+    fn check_enum(&mut self, node: usize, val: &Value) {
+        if let Some(values) = &self.schema.nodes[node].enumeration {
+            if !values.iter().any(|v| json_eq(v, val)) {
+                let message = format!("{} is not one of the enum values", val);
+                self.violation("enum", message);
+            }
+        }
+    }
+
+    fn scalar(&mut self, val: Value) {
+        if let Some(node) = self.enter_value() {
+            self.check_type(node, Type::of(&val), Some(&val));
+            self.check_enum(node, &val);
+
+            let schema = &self.schema.nodes[node];
+            if let Some(n) = number(&val) {
+                if let Some(min) = schema.minimum.filter(|min| n < *min) {
+                    self.violation("minimum", format!("{} is less than {}", val, min));
+                } else if let Some(max) = self.schema.nodes[node].maximum.filter(|max| n > *max) {
+                    self.violation("maximum", format!("{} is greater than {}", val, max));
+                }
+            }
+            if let (Value::String(s), Some(pattern)) = (&val, &self.schema.nodes[node].pattern) {
+                if !pattern.is_match(s) {
+                    let message = format!("{} does not match {:?}", val, pattern.as_str());
+                    self.violation("pattern", message);
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, is_object: bool) {
+        let node = self.enter_value();
+        if let Some(node) = node {
+            let actual = if is_object { Type::Object } else { Type::Array };
+            self.check_type(node, actual, None);
+            if self.schema.nodes[node].enumeration.is_some() {
+                self.captures
+                    .push((self.stack.len(), node, ValueBuilder::default()));
+            }
+        }
+        let seen = match node {
+            Some(node) if is_object => vec![false; self.schema.nodes[node].required.len()],
+            _ => vec![],
+        };
+        self.stack.push(Frame {
+            node,
+            is_object,
+            key: String::new(),
+            count: 0,
+            member: None,
+            seen,
+        });
+    }
+
+    fn end(&mut self) {
+        if let Some(frame) = self.stack.pop() {
+            if let Some(node) = frame.node {
+                let missing: Vec<String> = self.schema.nodes[node]
+                    .required
+                    .iter()
+                    .zip(&frame.seen)
+                    .filter(|(_, seen)| !**seen)
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                for key in missing {
+                    self.violation("required", format!("missing property {:?}", key));
+                }
+            }
+        }
+        let depth = self.stack.len();
+        if let Some(i) = self.captures.iter().position(|(d, _, _)| *d == depth) {
+            let (_, node, builder) = self.captures.remove(i);
+            if let Some(val) = builder.into_value() {
+                self.check_enum(node, &val);
+            }
+        }
+    }
+
+    fn map_key(&mut self, raw: &str) {
+        let key = unescape(raw).unwrap_or_else(|| raw.to_owned());
+        let nodes = &self.schema.nodes;
+        let frame = match self.stack.last_mut() {
+            Some(frame) => frame,
+            None => return,
+        };
+        frame.member = None;
+        let mut forbidden = false;
+        if let Some(node) = frame.node.map(|n| &nodes[n]) {
+            if let Some(i) = node.required.iter().position(|k| *k == key) {
+                frame.seen[i] = true;
+            }
+            match node.properties.iter().find(|(k, _)| *k == key) {
+                Some((_, child)) => frame.member = Some(*child),
+                None => match node.additional {
+                    Some(additional) if nodes[additional].reject => forbidden = true,
+                    additional => frame.member = additional,
+                },
+            }
+        }
+        frame.key = key;
+        if forbidden {
+            let message = format!("additional property {:?} is not allowed", frame.key);
+            self.violation("additionalProperties", message);
+        }
+    }
+
+    fn forward<F: FnMut(&mut dyn Handler) -> Status>(&mut self, mut f: F) -> Status {
+        for (_, _, builder) in &mut self.captures {
+            f(builder);
+        }
+        match &mut self.inner {
+            Some(inner) => match f(*inner) {
+                Status::SkipValue => Status::Continue,
+                status => status,
+            },
+            None => Status::Continue,
+        }
+    }
+}
+
+impl Handler for ValidatingHandler<'_> {
+    fn handle_null(&mut self, ctx: &Context) -> Status {
+        self.scalar(Value::Null);
+        self.forward(|h| h.handle_null(ctx))
+    }
+
+    fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+        self.scalar(Value::Double(val));
+        self.forward(|h| h.handle_double(ctx, val))
+    }
+
+    fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+        self.scalar(Value::Int(val));
+        self.forward(|h| h.handle_int(ctx, val))
+    }
+
+    fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+        self.scalar(Value::Bool(val));
+        self.forward(|h| h.handle_bool(ctx, val))
+    }
+
+    fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+        self.scalar(Value::String(
+            unescape(val).unwrap_or_else(|| val.to_owned()),
+        ));
+        self.forward(|h| h.handle_string(ctx, val))
+    }
+
+    fn handle_start_map(&mut self, ctx: &Context) -> Status {
+        self.start(true);
+        self.forward(|h| h.handle_start_map(ctx))
+    }
+
+    fn handle_end_map(&mut self, ctx: &Context) -> Status {
+        let status = self.forward(|h| h.handle_end_map(ctx));
+        self.end();
+        status
+    }
+
+    fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+        self.map_key(key);
+        self.forward(|h| h.handle_map_key(ctx, key))
+    }
+
+    fn handle_start_array(&mut self, ctx: &Context) -> Status {
+        self.start(false);
+        self.forward(|h| h.handle_start_array(ctx))
+    }
+
+    fn handle_end_array(&mut self, ctx: &Context) -> Status {
+        let status = self.forward(|h| h.handle_end_array(ctx));
+        self.end();
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{combinators::from_fn, Event};
+    use pretty_assertions::assert_eq;
+
+    const SCHEMA: &str = r#"{
+        "type": "object",
+        "required": ["features"],
+        "properties": {
+            "features": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["id", "kind"],
+                    "additionalProperties": false,
+                    "properties": {
+                        "id": { "type": "integer", "minimum": 1, "maximum": 10 },
+                        "kind": { "enum": ["a", ["b", 2]] },
+                        "name": { "type": ["string", "null"], "pattern": "^[A-Z]" }
+                    }
+                }
+            }
+        }
+    }"#;
+
+    fn validate(input: &str) -> Vec<String> {
+        let schema = SCHEMA.parse().unwrap();
+        let mut handler = ValidatingHandler::new(schema);
+        let mut parser = Parser::new(&mut handler);
+        parser.parse(&mut input.as_bytes()).unwrap();
+        handler.violations().iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(
+            validate(
+                r#"{ "features": [
+                    { "id": 1, "kind": "a", "name": "Apple" },
+                    { "id": 10.0, "kind": ["b", 2.0], "name": null }
+                ] }"#
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_violations() {
+        assert_eq!(
+            validate(
+                r#"{ "features": [
+                    { "id": 0, "kind": "c", "name": "apple", "extra": {} },
+                    { "id": "1", "kind": ["b", 3] },
+                    { "id": 11, "kind": "a", "name": 4 }
+                ] }"#
+            ),
+            vec![
+                ".features[0].id: 0 is less than 1 (minimum)",
+                ".features[0].kind: \"c\" is not one of the enum values (enum)",
+                ".features[0].name: \"apple\" does not match \"^[A-Z]\" (pattern)",
+                ".features[0].extra: additional property \"extra\" is not allowed (additionalProperties)",
+                ".features[1].id: expected integer, found string (type)",
+                ".features[1].kind: [\"b\",3] is not one of the enum values (enum)",
+                ".features[2].id: 11 is greater than 10 (maximum)",
+                ".features[2].name: expected string or null, found integer (type)",
+            ]
+        );
+        assert_eq!(
+            validate("[]"),
+            vec![".: expected object, found array (type)",]
+        );
+        assert_eq!(
+            validate("{}"),
+            vec![".: missing property \"features\" (required)"]
+        );
+    }
+
+    #[test]
+    fn test_forward() {
+        let schema: Schema = r#"{ "items": { "type": "integer" } }"#.parse().unwrap();
+        let mut builder = ValueBuilder::default();
+        let mut handler = ValidatingHandler::new(schema).forward_to(&mut builder);
+        let mut parser = Parser::new(&mut handler);
+        parser.parse(&mut "[1, true]".as_bytes()).unwrap();
+        assert_eq!(handler.violations().len(), 1);
+        drop(handler);
+        assert_eq!(
+            builder.into_value(),
+            Some(Value::Array(vec![Value::Int(1), Value::Bool(true)]))
+        );
+    }
+
+    #[test]
+    fn test_forward_skip_value() {
+        let schema: Schema = r#"{ "items": { "type": "integer" } }"#.parse().unwrap();
+        let mut skipper = from_fn(|_ctx, event| match event {
+            Event::StartArray => Status::SkipValue,
+            _ => Status::Continue,
+        });
+        let mut handler = ValidatingHandler::new(schema).forward_to(&mut skipper);
+        let mut parser = Parser::new(&mut handler);
+        parser.parse(&mut "[1, true, [2]]".as_bytes()).unwrap();
+        parser.finish_parse().unwrap();
+        assert_eq!(
+            handler
+                .violations()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "[1]: expected integer, found boolean (type)",
+                "[2]: expected integer, found array (type)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schema_error() {
+        assert!(matches!(
+            "{ \"type\": \"thing\" }".parse::<Schema>(),
+            Err(SchemaError::Invalid(_))
+        ));
+        assert!(matches!(
+            "{ \"pattern\": \"(\" }".parse::<Schema>(),
+            Err(SchemaError::Invalid(_))
+        ));
+        assert!(matches!("{".parse::<Schema>(), Err(SchemaError::Json(_))));
+    }
+}
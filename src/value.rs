@@ -0,0 +1,507 @@
+//! An owned JSON value, built from the events of a `Handler`.
+//!
+//! Use [`ValueBuilder`] to build a whole document, or `SelectedValues`
+//! (feature `ndjson`) to stream a large document and only build the values
+//! at a selector path.
+//!
+//! ```
+//!     use yajlish::{value::{Value, ValueBuilder}, Parser};
+//!
+//!     let mut builder = ValueBuilder::default();
+//!     let mut parser = Parser::new(&mut builder);
+//!     parser.parse(&mut "{ \"a\": [1, 2.5] }".as_bytes()).unwrap();
+//!
+//!     let value = builder.into_value().unwrap();
+//!     assert_eq!(value.get("a").and_then(|a| a.as_array()).map(|a| a.len()), Some(2));
+//!     assert_eq!(value.to_string(), "{\"a\":[1,2.5]}");
+//! ```
+//!
+
+#[cfg(feature = "ndjson")]
+use crate::ndjson_handler::{Select, Selector};
+use crate::{common::unescape, Context, Handler, Status};
+use std::{convert::TryFrom, fmt};
+
+/// A JSON value. Object members keep the order they were in.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    /// `null`
+    Null,
+    /// `true` or `false`
+    Bool(bool),
+    /// A number without a fraction or exponent that fits in an i64.
+    Int(i64),
+    /// Any other number.
+    Double(f64),
+    /// The string, unescaped.
+    String(String),
+    /// An array.
+    Array(Vec<Value>),
+    /// The members of an object, in order. Duplicate keys are kept.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The value of the first member with the key, if this is an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// True if this is `null`.
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// The bool, if this is one.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// The integer, if this is one.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// The number as a double, if this is an integer or a double.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Double(d) => Some(*d),
+            _ => None,
+        }
+    }
+
+    /// The string, if this is one.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The values, if this is an array.
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// The members, if this is an object.
+    pub fn as_object(&self) -> Option<&[(String, Value)]> {
+        match self {
+            Value::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(val: &str) -> Self {
+        Value::String(val.to_owned())
+    }
+}
+
+impl From<u64> for Value {
+    fn from(val: u64) -> Self {
+        i64::try_from(val).map_or(Value::Double(val as f64), Value::Int)
+    }
+}
+
+/// Write a string with its quotes, escaping what JSON requires.
+fn write_escaped<W: fmt::Write>(f: &mut W, val: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in val.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\u{8}' => f.write_str("\\b")?,
+            '\u{c}' => f.write_str("\\f")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(val) => write!(f, "{}", val),
+            Value::Int(val) => write!(f, "{}", val),
+            // JSON has no NaN or infinity.
+            Value::Double(val) if !val.is_finite() => write!(f, "null"),
+            // Debug keeps the decimal point or exponent so it is still a double.
+            Value::Double(val) => write!(f, "{:?}", val),
+            Value::String(val) => write_escaped(f, val),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, val) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", val)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_escaped(f, key)?;
+                    write!(f, ":{}", val)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// A `Handler` that builds a `Value` from the events of one JSON value.
+#[derive(Debug, Default)]
+pub struct ValueBuilder {
+    /// The open arrays and objects, with the key of the next member.
+    stack: Vec<(Value, Option<String>)>,
+    value: Option<Value>,
+}
+
+impl ValueBuilder {
+    /// The value, once it is complete.
+    pub fn value(&self) -> Option<&Value> {
+        self.value.as_ref()
+    }
+
+    /// The value, once it is complete.
+    pub fn into_value(self) -> Option<Value> {
+        self.value
+    }
+
+    fn push(&mut self, val: Value) -> Status {
+        match self.stack.last_mut() {
+            Some((Value::Array(values), _)) => values.push(val),
+            Some((Value::Object(members), key)) => {
+                members.push((key.take().unwrap_or_default(), val));
+            }
+            _ => self.value = Some(val),
+        }
+        Status::Continue
+    }
+
+    fn start(&mut self, val: Value) -> Status {
+        self.stack.push((val, None));
+        Status::Continue
+    }
+
+    fn end(&mut self) -> Status {
+        match self.stack.pop() {
+            Some((val, _)) => self.push(val),
+            None => Status::Continue,
+        }
+    }
+}
+
+impl Handler for ValueBuilder {
+    fn handle_null(&mut self, _ctx: &Context) -> Status {
+        self.push(Value::Null)
+    }
+
+    fn handle_double(&mut self, _ctx: &Context, val: f64) -> Status {
+        self.push(Value::Double(val))
+    }
+
+    fn handle_int(&mut self, _ctx: &Context, val: i64) -> Status {
+        self.push(Value::Int(val))
+    }
+
+    fn handle_bool(&mut self, _ctx: &Context, val: bool) -> Status {
+        self.push(Value::Bool(val))
+    }
+
+    fn handle_string(&mut self, _ctx: &Context, val: &str) -> Status {
+        self.push(Value::String(
+            unescape(val).unwrap_or_else(|| val.to_owned()),
+        ))
+    }
+
+    fn handle_start_map(&mut self, _ctx: &Context) -> Status {
+        self.start(Value::Object(vec![]))
+    }
+
+    fn handle_end_map(&mut self, _ctx: &Context) -> Status {
+        self.end()
+    }
+
+    fn handle_map_key(&mut self, _ctx: &Context, key: &str) -> Status {
+        if let Some((_, next)) = self.stack.last_mut() {
+            *next = Some(unescape(key).unwrap_or_else(|| key.to_owned()));
+        }
+        Status::Continue
+    }
+
+    fn handle_start_array(&mut self, _ctx: &Context) -> Status {
+        self.start(Value::Array(vec![]))
+    }
+
+    fn handle_end_array(&mut self, _ctx: &Context) -> Status {
+        self.end()
+    }
+}
+
+/// A `Handler` that builds a `Value` for each value of the array at a
+/// selector path, or for the value itself if it is not an array, and
+/// passes it to a closure. The rest of the document is not built.
+///
+/// Requires feature `ndjson`.
+#[cfg(feature = "ndjson")]
+pub struct SelectedValues<F> {
+    f: F,
+    state: SelectedState,
+    builder: ValueBuilder,
+}
+
+#[cfg(feature = "ndjson")]
+#[derive(Debug)]
+enum SelectedState {
+    Selecting(Select),
+    /// Building values, the root is Some(true) if the selected value is an array.
+    Building(Option<bool>),
+    Done,
+}
+
+#[cfg(feature = "ndjson")]
+impl<F> SelectedValues<F>
+where
+    F: FnMut(Value) -> Status,
+{
+    /// Constructor. The closure can return `Status::Abort` to stop parsing.
+    pub fn new(selectors: Vec<Selector>, f: F) -> Self {
+        let state = match Select::new(selectors) {
+            Some(select) => SelectedState::Selecting(select),
+            None => SelectedState::Building(None),
+        };
+        SelectedValues {
+            f,
+            state,
+            builder: ValueBuilder::default(),
+        }
+    }
+
+    fn select_array_value(&mut self, ctx: &Context) {
+        if let SelectedState::Selecting(select) = &mut self.state {
+            select.new_array_location(ctx);
+            if select.is_array_index_selector_selection(ctx) && select.remove_last() {
+                self.state = SelectedState::Building(None);
+            }
+        }
+    }
+
+    /// Pass an event to the builder, and the value to the closure if it is
+    /// complete.
+    fn build<G: FnOnce(&mut ValueBuilder) -> Status>(&mut self, g: G) -> Status {
+        let root = match &mut self.state {
+            SelectedState::Building(root) => root,
+            SelectedState::Selecting(_) => return Status::Continue,
+            SelectedState::Done => return Status::Abort,
+        };
+        g(&mut self.builder);
+        if self.builder.stack.is_empty() {
+            if root.is_none() {
+                *root = Some(false);
+            }
+            if let Some(value) = self.builder.value.take() {
+                if *root == Some(false) {
+                    self.state = SelectedState::Done;
+                }
+                return match (self.f)(value) {
+                    Status::Abort => Status::Abort,
+                    _ if matches!(self.state, SelectedState::Done) => Status::Abort,
+                    status => status,
+                };
+            }
+        }
+        Status::Continue
+    }
+
+    fn start<G: FnOnce(&mut ValueBuilder) -> Status>(
+        &mut self,
+        ctx: &Context,
+        is_array: bool,
+        g: G,
+    ) -> Status {
+        self.select_array_value(ctx);
+        if let SelectedState::Building(root @ None) = &mut self.state {
+            if is_array {
+                *root = Some(true);
+                return Status::Continue;
+            }
+            *root = Some(false);
+        }
+        self.build(g)
+    }
+
+    fn value<G: FnOnce(&mut ValueBuilder) -> Status>(&mut self, ctx: &Context, g: G) -> Status {
+        self.select_array_value(ctx);
+        self.build(g)
+    }
+
+    fn end<G: FnOnce(&mut ValueBuilder) -> Status>(&mut self, g: G) -> Status {
+        if let SelectedState::Building(Some(true)) = self.state {
+            if self.builder.stack.is_empty() {
+                self.state = SelectedState::Done;
+                return Status::Abort;
+            }
+        }
+        self.build(g)
+    }
+}
+
+#[cfg(feature = "ndjson")]
+impl<F> Handler for SelectedValues<F>
+where
+    F: FnMut(Value) -> Status,
+{
+    fn handle_null(&mut self, ctx: &Context) -> Status {
+        self.value(ctx, |b| b.handle_null(ctx))
+    }
+
+    fn handle_double(&mut self, ctx: &Context, val: f64) -> Status {
+        self.value(ctx, |b| b.handle_double(ctx, val))
+    }
+
+    fn handle_int(&mut self, ctx: &Context, val: i64) -> Status {
+        self.value(ctx, |b| b.handle_int(ctx, val))
+    }
+
+    fn handle_bool(&mut self, ctx: &Context, val: bool) -> Status {
+        self.value(ctx, |b| b.handle_bool(ctx, val))
+    }
+
+    fn handle_string(&mut self, ctx: &Context, val: &str) -> Status {
+        self.value(ctx, |b| b.handle_string(ctx, val))
+    }
+
+    fn handle_start_map(&mut self, ctx: &Context) -> Status {
+        self.start(ctx, false, |b| b.handle_start_map(ctx))
+    }
+
+    fn handle_end_map(&mut self, ctx: &Context) -> Status {
+        self.end(|b| b.handle_end_map(ctx))
+    }
+
+    fn handle_map_key(&mut self, ctx: &Context, key: &str) -> Status {
+        match &mut self.state {
+            SelectedState::Selecting(select) => {
+                if select.is_identifier_selector_selection(ctx, key) && select.remove_last() {
+                    self.state = SelectedState::Building(None);
+                }
+                Status::Continue
+            }
+            SelectedState::Building(_) => self.builder.handle_map_key(ctx, key),
+            SelectedState::Done => Status::Abort,
+        }
+    }
+
+    fn handle_start_array(&mut self, ctx: &Context) -> Status {
+        self.start(ctx, true, |b| b.handle_start_array(ctx))
+    }
+
+    fn handle_end_array(&mut self, ctx: &Context) -> Status {
+        self.end(|b| b.handle_end_array(ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_display() {
+        let value = Value::Object(vec![
+            (
+                "a\"b".to_owned(),
+                Value::Array(vec![Value::Int(1), Value::Double(2.0)]),
+            ),
+            ("c".to_owned(), Value::from("x\n\u{1}y")),
+            ("d".to_owned(), Value::Object(vec![])),
+        ]);
+        assert_eq!(
+            value.to_string(),
+            r#"{"a\"b":[1,2.0],"c":"x\n\u0001y","d":{}}"#
+        );
+
+        let value = Value::Array(vec![
+            Value::Double(f64::NAN),
+            Value::Double(f64::INFINITY),
+            Value::Double(f64::NEG_INFINITY),
+        ]);
+        assert_eq!(value.to_string(), "[null,null,null]");
+    }
+
+    #[test]
+    fn test_value_builder() {
+        let mut builder = ValueBuilder::default();
+        let mut parser = crate::Parser::new(&mut builder);
+        parser
+            .parse(&mut r#"{ "b": [true, null], "a": "\u00e9", "n": -1.5e3 }"#.as_bytes())
+            .unwrap();
+
+        let value = builder.into_value().unwrap();
+        assert_eq!(
+            value,
+            Value::Object(vec![
+                (
+                    "b".to_owned(),
+                    Value::Array(vec![Value::Bool(true), Value::Null])
+                ),
+                ("a".to_owned(), Value::from("é")),
+                ("n".to_owned(), Value::Double(-1500.0)),
+            ])
+        );
+        assert_eq!(value.get("a").and_then(Value::as_str), Some("é"));
+        assert_eq!(value.get("n").and_then(Value::as_f64), Some(-1500.0));
+    }
+
+    #[cfg(feature = "ndjson")]
+    #[test]
+    fn test_selected_values() {
+        use crate::predicate::PathParser;
+
+        fn selected(input: &str, path: &str) -> Vec<String> {
+            let mut values = vec![];
+            let mut handler = SelectedValues::new(PathParser::new().parse(path).unwrap(), |v| {
+                values.push(v.to_string());
+                Status::Continue
+            });
+            let mut parser = crate::Parser::new(&mut handler);
+            parser.parse(&mut input.as_bytes()).unwrap();
+            values
+        }
+
+        let input = r#"{ "a": { "b": [{ "c": [1, 2] }, 3, [], "x"], "d": true }, "e": [4] }"#;
+        assert_eq!(
+            selected(input, ".a.b"),
+            vec![r#"{"c":[1,2]}"#, "3", "[]", r#""x""#]
+        );
+        assert_eq!(selected(input, ".a.b[0].c"), vec!["1", "2"]);
+        assert_eq!(selected(input, ".a.d"), vec!["true"]);
+        assert_eq!(selected(input, ".a.b[0]"), vec![r#"{"c":[1,2]}"#]);
+        assert_eq!(selected(input, ".e"), vec!["4"]);
+        assert_eq!(selected(input, ".f"), Vec::<String>::new());
+    }
+}
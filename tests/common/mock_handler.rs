@@ -8,6 +8,11 @@ impl<W: std::io::Write> MockHandler<W> {
     pub fn new(out: W) -> Self {
         MockHandler { write: out }
     }
+
+    #[allow(unused)]
+    pub fn into_inner(self) -> W {
+        self.write
+    }
 }
 
 impl<W> Handler for MockHandler<W>
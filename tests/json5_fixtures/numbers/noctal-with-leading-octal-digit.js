@@ -0,0 +1 @@
+0780
\ No newline at end of file
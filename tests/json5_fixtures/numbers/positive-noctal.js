@@ -0,0 +1 @@
++098
\ No newline at end of file
@@ -0,0 +1,111 @@
+//! Cases written from the JSON5 spec, and the cases of the json5-tests
+//! suite in `json5_fixtures`.
+
+mod common;
+
+use common::mock_handler::MockHandler;
+use pretty_assertions::assert_eq;
+use yajlish::{Parser, ParserOptions};
+
+fn parse_json5(input: &str) -> Result<String, String> {
+    let mut parser = Parser::with_handler(MockHandler::new(vec![])).options(ParserOptions::json5());
+    parser
+        .parse(&mut input.as_bytes())
+        .and_then(|_| parser.finish_parse())
+        .map_err(|e| e.to_string())?;
+    Ok(String::from_utf8(parser.into_inner().into_inner()).unwrap())
+}
+
+fn assert_json5(input: &str, output: &str) {
+    assert_eq!(parse_json5(input).as_deref(), Ok(output), "{}", input);
+}
+
+#[test]
+fn test_json5_numbers() {
+    assert_json5("0x1F", "integer: 31\n");
+    assert_json5("-0XaB", "integer: -171\n");
+    assert_json5("+1", "integer: 1\n");
+    assert_json5(".5", "double: 0.5\n");
+    assert_json5("5.", "double: 5\n");
+    assert_json5("-.5e-1", "double: -0.05\n");
+    assert_json5("+Infinity", "double: inf\n");
+    assert_json5("-Infinity", "double: -inf\n");
+    assert_json5("NaN", "double: NaN\n");
+    assert_json5("-NaN", "double: NaN\n");
+
+    for invalid in &["0x", "0xG", "+", ".", "1.2.3", "Infinityx", "+inf", "- 1"] {
+        assert!(parse_json5(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_json5_strings() {
+    assert_json5("'a\"b'", "string: '\"a\\\"b\"'\n");
+    assert_json5("\"a\\'b\"", "string: '\"a'b\"'\n");
+    assert_json5(
+        "'line\\\ncontinued\\\r\nagain'",
+        "string: '\"linecontinuedagain\"'\n",
+    );
+    assert_json5("'\\v\\0\\x41\\a'", "string: '\"\\u000b\\u0000\\u0041a\"'\n");
+    assert_json5("'\\n\\u00e9'", "string: '\"\\n\\u00e9\"'\n");
+
+    assert!(parse_json5("'\\x4'").is_err());
+    assert!(parse_json5("'unterminated").is_err());
+}
+
+#[test]
+fn test_json5_objects_and_arrays() {
+    assert_json5(
+        "// comment\n{\n  unquoted: 'and you can quote me on that',\n  $_ident1: [1, 2,],\n  \u{e9}t\u{e9}: null, /* trailing */\n  Infinity: true,\n}\n",
+        "map open '{'\nkey: \"unquoted\"\nstring: '\"and you can quote me on that\"'\nkey: \"$_ident1\"\narray open '['\ninteger: 1\ninteger: 2\narray close ']'\nkey: \"\u{e9}t\u{e9}\"\nnull\nkey: \"Infinity\"\nbool: true\nmap close '}'\n",
+    );
+    assert_json5(
+        "\u{feff}[\u{a0}1,\u{2028}\u{b}2\u{c}]",
+        "array open '['\ninteger: 1\ninteger: 2\narray close ']'\n",
+    );
+
+    for invalid in &[
+        "{a b: 1}", "[1,,]", "{,}", "[,]", "{1: 2}", "/* a", "{a: 1 /}",
+    ] {
+        assert!(parse_json5(invalid).is_err(), "{}", invalid);
+    }
+}
+
+fn parse_strict(input: &str) -> Result<String, String> {
+    let mut parser = Parser::with_handler(MockHandler::new(vec![]));
+    parser
+        .parse(&mut input.as_bytes())
+        .and_then(|_| parser.finish_parse())
+        .map_err(|e| e.to_string())?;
+    Ok(String::from_utf8(parser.into_inner().into_inner()).unwrap())
+}
+
+/// The json5-tests suite: `.json` files are valid JSON and JSON5, `.json5`
+/// files are valid JSON5, and `.js` and `.txt` files aren't valid JSON5.
+#[test]
+fn test_json5_tests_suite() {
+    let mut count = 0;
+    let mut failures = vec![];
+    for category in std::fs::read_dir("tests/json5_fixtures").unwrap() {
+        let category = category.unwrap().path();
+        if !category.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&category).unwrap() {
+            let path = entry.unwrap().path();
+            let input = std::fs::read_to_string(&path).unwrap();
+            let result = parse_json5(&input);
+            let passed = match path.extension().and_then(|e| e.to_str()) {
+                Some("json") => result.is_ok() && result == parse_strict(&input),
+                Some("json5") => result.is_ok(),
+                _ => result.is_err(),
+            };
+            if !passed {
+                failures.push(format!("{}: {:?}", path.display(), result));
+            }
+            count += 1;
+        }
+    }
+    assert!(count > 100);
+    assert_eq!(failures, Vec::<String>::new());
+}
@@ -1,6 +1,6 @@
 mod common;
 
-use common::assert_output_equals;
+use common::{assert_output_equals, mock_handler::MockHandler};
 
 #[test]
 fn test_bool() {
@@ -57,7 +57,9 @@ fn test_array_with_string() {
     );
 }
 
-use yajlish::{Context, Enclosing, Handler, Parser, ParserStatus, Status};
+use yajlish::{
+    Context, Enclosing, Event, Handler, ParseError, Parser, ParserOptions, ParserStatus, Status,
+};
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
@@ -267,3 +269,311 @@ fn test_parser_context_map() {
 
     parser.parse(&mut input).unwrap();
 }
+
+#[test]
+fn test_owned_handler() {
+    let mut parser = Parser::with_handler(MockHandler::new(vec![]));
+    parser.parse(&mut "[1, null]".as_bytes()).unwrap();
+    assert_eq!(parser.finish_parse(), Ok(()));
+    assert_eq!(
+        parser.into_inner().into_inner(),
+        b"array open '['\ninteger: 1\nnull\narray close ']'\n".to_vec()
+    );
+
+    let mut handler = MockHandler::new(vec![]);
+    let mut parser = Parser::new(&mut handler);
+    parser.parse(&mut "null".as_bytes()).unwrap();
+    let _: &mut MockHandler<_> = parser.into_inner();
+    assert_eq!(handler.into_inner(), b"null\n".to_vec());
+}
+
+#[test]
+fn test_boxed_handler() {
+    let mut out = vec![];
+    let handler: Box<dyn Handler + '_> = Box::new(MockHandler::new(&mut out));
+    let mut parser = Parser::with_handler(handler);
+    parser.parse(&mut "{ \"a\": true }".as_bytes()).unwrap();
+    drop(parser);
+    assert_eq!(
+        out,
+        b"map open '{'\nkey: \"a\"\nbool: true\nmap close '}'\n"
+    );
+
+    let mut out = vec![];
+    let mut handler = MockHandler::new(&mut out);
+    let mut by_ref = &mut handler;
+    Parser::new(&mut by_ref)
+        .parse(&mut "null".as_bytes())
+        .unwrap();
+    assert_eq!(out, b"null\n");
+}
+
+fn parse_with_options(input: &str, options: ParserOptions) -> Result<(), ParseError> {
+    let mut parser = Parser::with_handler(MockHandler::new(vec![])).options(options);
+    parser.parse(&mut input.as_bytes())
+}
+
+#[test]
+fn test_limits() {
+    let input = r#"{ "abc": [[1.25, "xyz"], [true]] }"#;
+    assert_eq!(parse_with_options(input, ParserOptions::new()), Ok(()));
+
+    let options = ParserOptions::new()
+        .max_depth(3)
+        .max_string_length(3)
+        .max_number_length(4)
+        .max_bytes(input.len() as u64)
+        .max_elements(7);
+    assert_eq!(parse_with_options(input, options), Ok(()));
+
+    assert_eq!(
+        parse_with_options(input, options.max_depth(2)),
+        Err(ParseError::DepthLimitExceeded(2))
+    );
+    assert_eq!(
+        parse_with_options(input, options.max_string_length(2)),
+        Err(ParseError::StringLengthExceeded(2))
+    );
+    assert_eq!(
+        parse_with_options(input, options.max_number_length(3)),
+        Err(ParseError::NumberLengthExceeded(3))
+    );
+    assert_eq!(
+        parse_with_options(input, options.max_bytes(20)),
+        Err(ParseError::DocumentSizeExceeded(20))
+    );
+    assert_eq!(
+        parse_with_options(input, options.max_elements(6)),
+        Err(ParseError::ElementCountExceeded(6))
+    );
+}
+
+#[test]
+fn test_limits_stop_reading() {
+    // Only a little more than the limit is read from a long string, key or
+    // number, even when the reader returns the rest of the input at once.
+    let long = "a".repeat(1 << 20);
+    let digits = "1".repeat(1 << 20);
+    let cases = [
+        (
+            format!("[\"{}\"]", long),
+            ParseError::StringLengthExceeded(10),
+        ),
+        (
+            format!("{{\"{}\": 1}}", long),
+            ParseError::StringLengthExceeded(10),
+        ),
+        (
+            format!("{{{}: 1}}", long),
+            ParseError::StringLengthExceeded(10),
+        ),
+        (
+            format!("[{}]", digits),
+            ParseError::NumberLengthExceeded(10),
+        ),
+    ];
+    let options = ParserOptions::new()
+        .max_string_length(10)
+        .max_number_length(10)
+        .allow_unquoted_keys(true);
+    for (input, error) in &cases {
+        let mut parser = Parser::with_handler(MockHandler::new(vec![])).options(options);
+        let mut read = input.as_bytes();
+        assert_eq!(parser.parse(&mut read).as_ref(), Err(error));
+        assert!(
+            input.len() - read.len() < 20,
+            "{}",
+            input.len() - read.len()
+        );
+    }
+}
+
+#[test]
+fn test_limits_across_parses() {
+    // The handler aborts at each event, and the parse is continued.
+    let parse_resumed = |input: &[u8], options: ParserOptions| {
+        let handler = yajlish::combinators::from_fn(|_ctx, _event| Status::Abort);
+        let mut parser = Parser::with_handler(handler).options(options);
+        let mut read = input;
+        while parser.finish_parse().is_err() {
+            parser.parse(&mut read)?;
+        }
+        Ok(())
+    };
+    let input = b"[1,2,3,4]";
+    assert_eq!(parse_resumed(input, ParserOptions::new()), Ok(()));
+    assert_eq!(
+        parse_resumed(input, ParserOptions::new().max_bytes(5)),
+        Err(ParseError::DocumentSizeExceeded(5))
+    );
+    assert_eq!(
+        parse_resumed(input, ParserOptions::new().max_elements(2)),
+        Err(ParseError::ElementCountExceeded(2))
+    );
+
+    // Offsets are in the whole input.
+    assert!(matches!(
+        parse_resumed(b"[\"a\",\"\xFF\"]", ParserOptions::new()),
+        Err(ParseError::Utf8Error { offset: 6, .. })
+    ));
+}
+
+#[test]
+fn test_lenient() {
+    let input = "{\n  // a comment\n  name: 'yajl\\'s', /* another */\n  \"list\": [1, NaN,],\n}";
+    assert!(parse_with_options(input, ParserOptions::new()).is_err());
+
+    let options = ParserOptions::new()
+        .allow_comments(true)
+        .allow_trailing_commas(true)
+        .allow_single_quotes(true)
+        .allow_unquoted_keys(true)
+        .allow_nan_infinity(true);
+    let mut parser = Parser::with_handler(MockHandler::new(vec![])).options(options);
+    parser.parse(&mut input.as_bytes()).unwrap();
+    assert_eq!(parser.finish_parse(), Ok(()));
+    assert_eq!(
+        String::from_utf8(parser.into_inner().into_inner()).unwrap(),
+        "map open '{'\nkey: \"name\"\nstring: '\"yajl's\"'\nkey: \"list\"\narray open '['\ninteger: 1\ndouble: NaN\narray close ']'\nmap close '}'\n"
+    );
+
+    assert!(parse_with_options("[1,]", ParserOptions::new()).is_err());
+    assert!(parse_with_options(r#"["a\qb"]"#, ParserOptions::new()).is_err());
+    assert!(parse_with_options("{\"a\": 1,}", ParserOptions::new()).is_err());
+}
+
+#[test]
+fn test_partial_values() {
+    let partial = |input: &str| {
+        let options = ParserOptions::new().allow_partial_values(true);
+        let mut parser = Parser::with_handler(MockHandler::new(vec![])).options(options);
+        parser.parse(&mut input.as_bytes()).unwrap();
+        assert_eq!(parser.finish_parse(), Ok(()));
+        let truncated = parser.truncated();
+        let out = parser.into_inner().into_inner();
+        (String::from_utf8(out).unwrap(), truncated)
+    };
+
+    assert_eq!(
+        partial("{ \"a\": [1, { \"b\": \"abc"),
+        (
+            "map open '{'\nkey: \"a\"\narray open '['\ninteger: 1\nmap open '{'\nmap close '}'\narray close ']'\nmap close '}'\n".to_owned(),
+            true
+        )
+    );
+    assert_eq!(
+        partial("[true, fal"),
+        (
+            "array open '['\nbool: true\narray close ']'\n".to_owned(),
+            true
+        )
+    );
+    assert_eq!(
+        partial("[1]"),
+        (
+            "array open '['\ninteger: 1\narray close ']'\n".to_owned(),
+            false
+        )
+    );
+
+    // A number that ends at EOF may have been cut off.
+    for input in &["[1, -", "[1, 1e", "[1, 12", "[1, 1.5"] {
+        assert_eq!(
+            partial(input),
+            (
+                "array open '['\ninteger: 1\narray close ']'\n".to_owned(),
+                true
+            ),
+            "{}",
+            input
+        );
+    }
+    assert_eq!(partial("12"), ("integer: 12\n".to_owned(), false));
+
+    // A key without a value is dropped.
+    for input in &[
+        "{\"a\"",
+        "{\"a\":",
+        "{\"a\": 1",
+        "{\"a\": \"b",
+        "{\"a\": tr",
+    ] {
+        assert_eq!(
+            partial(input),
+            ("map open '{'\nmap close '}'\n".to_owned(), true),
+            "{}",
+            input
+        );
+    }
+    assert_eq!(
+        partial("{\"a\": 1, \"b\": [2"),
+        (
+            "map open '{'\nkey: \"a\"\ninteger: 1\nkey: \"b\"\narray open '['\narray close ']'\nmap close '}'\n".to_owned(),
+            true
+        )
+    );
+
+    // The tokens read after a key are kept when the parse is resumed.
+    let mut keys = 0;
+    let mut events = vec![];
+    let mut handler = yajlish::combinators::from_fn(|_ctx, event| {
+        let key = matches!(event, Event::Key(_));
+        events.push(event);
+        if key {
+            keys += 1;
+            Status::Abort
+        } else {
+            Status::Continue
+        }
+    });
+    let mut parser =
+        Parser::new(&mut handler).options(ParserOptions::new().allow_partial_values(true));
+    let mut read = "{\"a\": [1], \"b\": 2, \"c\"".as_bytes();
+    for _ in 0..3 {
+        parser.parse(&mut read).unwrap();
+    }
+    assert_eq!(parser.finish_parse(), Ok(()));
+    drop(parser);
+    assert_eq!(keys, 2);
+    assert_eq!(
+        events,
+        vec![
+            Event::StartMap,
+            Event::Key("\"a\"".to_owned()),
+            Event::StartArray,
+            Event::Int(1),
+            Event::EndArray,
+            Event::Key("\"b\"".to_owned()),
+            Event::Int(2),
+            Event::EndMap,
+        ]
+    );
+
+    assert!(parse_with_options("[1, \"abc", ParserOptions::new()).is_err());
+    assert!(parse_with_options("[1, fal", ParserOptions::new()).is_err());
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_parse_file() {
+    for entry in std::fs::read_dir("tests/valid_json_fixtures").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() != Some("json".as_ref()) {
+            continue;
+        }
+        let mut out = vec![];
+        let mut handler = MockHandler::new(&mut out);
+        let mut parser = Parser::new(&mut handler);
+        parser.parse_file(&path).unwrap();
+        assert_eq!(parser.finish_parse(), Ok(()));
+        let gold = std::fs::read(format!("{}.gold", path.display())).unwrap();
+        assert_eq!(out, gold, "{}", path.display());
+    }
+
+    let mut out = vec![];
+    let mut handler = MockHandler::new(&mut out);
+    assert!(matches!(
+        Parser::new(&mut handler).parse_file("tests/missing.json"),
+        Err(yajlish::ParseError::ReadError(_))
+    ));
+}
@@ -0,0 +1,230 @@
+#![cfg(feature = "ndjson")]
+
+use proptest::{prelude::*, test_runner::FileFailurePersistence};
+use yajlish::{
+    ndjson_handler::{NdJsonHandler, Selector},
+    Context, Handler, Parser, Status,
+};
+
+#[derive(Debug, Clone)]
+enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn write(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(&b.to_string()),
+            Json::Int(i) => out.push_str(&i.to_string()),
+            Json::Double(d) => out.push_str(&format!("{:?}", d)),
+            Json::String(s) => write_string(s, out),
+            Json::Array(values) => {
+                out.push('[');
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    v.write(out);
+                }
+                out.push(']');
+            }
+            Json::Object(members) => {
+                out.push('{');
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    write_string(k, out);
+                    out.push_str(": ");
+                    v.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out);
+        out
+    }
+
+    /// Every path in the document, with the value at that path.
+    fn paths(&self, path: &mut Vec<Selector>, found: &mut Vec<(Vec<Selector>, Json)>) {
+        found.push((path.clone(), self.clone()));
+        match self {
+            Json::Array(values) => {
+                for (i, v) in values.iter().enumerate() {
+                    path.push(Selector::Index(i));
+                    v.paths(path, found);
+                    path.pop();
+                }
+            }
+            Json::Object(members) => {
+                for (k, v) in members {
+                    let mut key = String::new();
+                    write_string(k, &mut key);
+                    path.push(Selector::Identifier(key));
+                    v.paths(path, found);
+                    path.pop();
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn arb_json() -> impl Strategy<Value = Json> {
+    let leaf = prop_oneof![
+        Just(Json::Null),
+        any::<bool>().prop_map(Json::Bool),
+        any::<i64>().prop_map(Json::Int),
+        any::<f64>()
+            .prop_filter("finite", |d| d.is_finite())
+            .prop_map(Json::Double),
+        "[a-z\"\\\\\n\t\u{1} é☃]{0,8}".prop_map(Json::String),
+    ];
+    leaf.prop_recursive(4, 64, 6, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..6).prop_map(Json::Array),
+            prop::collection::btree_map("[a-z\"]{0,4}", inner, 0..6)
+                .prop_map(|m| Json::Object(m.into_iter().collect())),
+        ]
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    String(String),
+    Key(String),
+    StartMap,
+    EndMap,
+    StartArray,
+    EndArray,
+}
+
+#[derive(Default)]
+struct EventHandler(Vec<Event>);
+
+impl EventHandler {
+    fn push(&mut self, event: Event) -> Status {
+        self.0.push(event);
+        Status::Continue
+    }
+}
+
+impl Handler for EventHandler {
+    fn handle_null(&mut self, _ctx: &Context) -> Status {
+        self.push(Event::Null)
+    }
+
+    fn handle_double(&mut self, _ctx: &Context, val: f64) -> Status {
+        self.push(Event::Double(val))
+    }
+
+    fn handle_int(&mut self, _ctx: &Context, val: i64) -> Status {
+        self.push(Event::Int(val))
+    }
+
+    fn handle_bool(&mut self, _ctx: &Context, val: bool) -> Status {
+        self.push(Event::Bool(val))
+    }
+
+    fn handle_string(&mut self, _ctx: &Context, val: &str) -> Status {
+        self.push(Event::String(val.to_owned()))
+    }
+
+    fn handle_start_map(&mut self, _ctx: &Context) -> Status {
+        self.push(Event::StartMap)
+    }
+
+    fn handle_end_map(&mut self, _ctx: &Context) -> Status {
+        self.push(Event::EndMap)
+    }
+
+    fn handle_map_key(&mut self, _ctx: &Context, key: &str) -> Status {
+        self.push(Event::Key(key.to_owned()))
+    }
+
+    fn handle_start_array(&mut self, _ctx: &Context) -> Status {
+        self.push(Event::StartArray)
+    }
+
+    fn handle_end_array(&mut self, _ctx: &Context) -> Status {
+        self.push(Event::EndArray)
+    }
+}
+
+fn events(input: &str) -> Vec<Event> {
+    let mut handler = EventHandler::default();
+    let mut parser = Parser::new(&mut handler);
+    parser.parse(&mut input.as_bytes()).unwrap();
+    parser.finish_parse().unwrap();
+    handler.0
+}
+
+fn ndjson(input: &str, selectors: Vec<Selector>) -> String {
+    let mut out = vec![];
+    let mut handler = NdJsonHandler::new(&mut out, selectors);
+    let mut parser = Parser::new(&mut handler);
+    parser.parse(&mut input.as_bytes()).unwrap();
+
+    String::from_utf8(out).unwrap()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        failure_persistence: Some(Box::new(FileFailurePersistence::Direct(
+            "proptest-regressions/test_prop_test.txt",
+        ))),
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn test_ndjson_round_trip(json in arb_json()) {
+        let input = json.to_json();
+
+        let mut paths = vec![];
+        json.paths(&mut vec![], &mut paths);
+
+        for (selectors, value) in paths {
+            let out = ndjson(&input, selectors.clone());
+            let lines = out.lines().collect::<Vec<_>>();
+
+            if let Json::Array(values) = value {
+                prop_assert_eq!(lines.len(), values.len(), "selectors: {:?}, output: {:?}", selectors, out);
+                for (line, value) in lines.into_iter().zip(values) {
+                    prop_assert_eq!(events(line), events(&value.to_json()), "selectors: {:?}", selectors);
+                }
+            } else {
+                prop_assert_eq!(lines.len(), 1, "selectors: {:?}, output: {:?}", selectors, out);
+                prop_assert_eq!(events(lines[0]), events(&value.to_json()), "selectors: {:?}", selectors);
+            }
+        }
+    }
+}